@@ -0,0 +1,96 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::Instant;
+use bevy_tutorial::flow_field::*;
+use bevy_tutorial::health::*;
+use bevy_tutorial::nav::*;
+use bevy_tutorial::resources::*;
+use bevy_tutorial::steering::*;
+use bevy_tutorial::target::*;
+use test::Bencher;
+
+const TARGETS: usize = 1000;
+const FRAME: Duration = Duration::from_millis(16);
+// Slow enough that the targets stay spread over the map for a whole run, rather than reaching the
+// goal.
+const SPEED: f32 = 0.05;
+
+// An app steering `TARGETS` ground targets by the flow fields and around each other. Time only
+// moves when `step` is called.
+fn app_with_targets() -> (App, Instant) {
+    let now = Instant::now();
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(HealthPlugin {})
+        .add_plugin(TargetPlugin {})
+        .add_plugin(NavPlugin {})
+        .add_plugin(SteeringPlugin {})
+        .insert_resource(PlayerStats::default())
+        .insert_resource(Route::default())
+        .insert_resource(TimeUpdateStrategy::ManualInstant(now));
+    for i in 0..TARGETS {
+        let x = (i % 40) as f32 / 40.0 * 4.0 - 2.0;
+        let z = (i / 40) as f32 / 25.0 * 4.0 - 2.0;
+        app.world.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(x, 0.0, z)),
+            TargetBundle::new(10.0, Vec3::X * SPEED, 0.2),
+            Steering::new(Vec3::X * SPEED),
+        ));
+    }
+    // Builds the flow fields.
+    app.update();
+    (app, now)
+}
+
+// Runs a frame `FRAME` after the last one.
+fn step(app: &mut App, now: &mut Instant) {
+    *now += FRAME;
+    app.insert_resource(TimeUpdateStrategy::ManualInstant(*now));
+    app.update();
+}
+
+#[bench]
+fn steer_targets(b: &mut Bencher) {
+    let (mut app, mut now) = app_with_targets();
+    b.iter(|| step(&mut app, &mut now));
+}
+
+// A tower built and sold, as a 2x2 block of cells in the middle of the map.
+fn toggle_obstacle(grid: &mut NavGrid, blocked: bool) {
+    let center = grid.size() / 2;
+    for cell in [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE] {
+        grid.set_blocked(center + cell, blocked);
+    }
+}
+
+#[bench]
+fn update_field_incrementally(b: &mut Bencher) {
+    let mut grid = NavGrid::default();
+    let mut field = FlowField::new(&grid, Route::default().goal);
+    let mut blocked = false;
+    b.iter(|| {
+        blocked = !blocked;
+        toggle_obstacle(&mut grid, blocked);
+        field.update(&grid);
+    });
+}
+
+#[bench]
+fn rebuild_field(b: &mut Bencher) {
+    let mut grid = NavGrid::default();
+    let goal = Route::default().goal;
+    let mut blocked = false;
+    b.iter(|| {
+        blocked = !blocked;
+        toggle_obstacle(&mut grid, blocked);
+        FlowField::new(&grid, goal)
+    });
+}
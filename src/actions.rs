@@ -0,0 +1,644 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+pub const BINDINGS_PATH: &str = "config/bindings.ron";
+
+// Analog inputs below this magnitude are treated as released.
+pub const AXIS_DEADZONE: f32 = 0.2;
+
+// Everything the player can do, independent of the device used to do it.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Reflect,
+    FromReflect,
+    Serialize,
+    Deserialize,
+)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    RotateLeft,
+    RotateRight,
+    PitchUp,
+    PitchDown,
+    ZoomIn,
+    ZoomOut,
+    // Held while moving the mouse to pan the camera.
+    DragPan,
+    // Modifier which turns a camera drag into an orbit around the focus point.
+    Orbit,
+    // Move the camera to the selected entity.
+    Focus,
+    // Keep the camera on the selected entity as it moves.
+    ToggleFollow,
+    Pause,
+    Select,
+    // Modifier which adds to or removes from the selection instead of replacing it.
+    AddToSelection,
+    // Select every entity of the same type as the current selection.
+    SelectAllOfType,
+    Cancel,
+    Build,
+    // Switch between playing and editing the level.
+    ToggleEditor,
+    // Editor tools and commands.
+    NextTool,
+    Undo,
+    Redo,
+    Delete,
+    Save,
+    // Open or close the settings menu.
+    ToggleSettings,
+}
+
+impl Action {
+    pub const ALL: [Action; 27] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::PitchUp,
+        Action::PitchDown,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::DragPan,
+        Action::Orbit,
+        Action::Focus,
+        Action::ToggleFollow,
+        Action::Pause,
+        Action::Select,
+        Action::AddToSelection,
+        Action::SelectAllOfType,
+        Action::Cancel,
+        Action::Build,
+        Action::ToggleEditor,
+        Action::NextTool,
+        Action::Undo,
+        Action::Redo,
+        Action::Delete,
+        Action::Save,
+        Action::ToggleSettings,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+// A single physical input which can trigger an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+    // Half of a gamepad axis, so that a stick can drive 2 opposing actions.
+    GamepadAxis(GamepadAxisType, AxisDirection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindingConflict {
+    pub binding: Binding,
+    pub existing: Action,
+}
+
+impl fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is already bound to {:?}",
+            self.binding, self.existing
+        )
+    }
+}
+
+impl std::error::Error for BindingConflict {}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    Conflict(BindingConflict),
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingsError::Io(err) => write!(f, "failed to access bindings file: {err}"),
+            BindingsError::Parse(err) => write!(f, "failed to parse bindings file: {err}"),
+            BindingsError::Serialize(err) => write!(f, "failed to serialize bindings: {err}"),
+            BindingsError::Conflict(conflict) => write!(f, "conflicting bindings: {conflict}"),
+        }
+    }
+}
+
+impl std::error::Error for BindingsError {}
+
+impl From<std::io::Error> for BindingsError {
+    fn from(err: std::io::Error) -> Self {
+        BindingsError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for BindingsError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        BindingsError::Parse(err)
+    }
+}
+
+impl From<ron::Error> for BindingsError {
+    fn from(err: ron::Error) -> Self {
+        BindingsError::Serialize(err)
+    }
+}
+
+impl From<BindingConflict> for BindingsError {
+    fn from(conflict: BindingConflict) -> Self {
+        BindingsError::Conflict(conflict)
+    }
+}
+
+// Maps actions to the inputs which trigger them. A binding may only trigger a single action.
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+pub struct InputBindings {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use AxisDirection::*;
+        use GamepadAxisType::*;
+
+        let mut bindings = InputBindings {
+            bindings: BTreeMap::new(),
+        };
+        for (action, binding) in [
+            (Action::MoveForward, Binding::Key(KeyCode::W)),
+            (
+                Action::MoveForward,
+                Binding::GamepadAxis(LeftStickY, Positive),
+            ),
+            (Action::MoveBack, Binding::Key(KeyCode::S)),
+            (Action::MoveBack, Binding::GamepadAxis(LeftStickY, Negative)),
+            (Action::MoveLeft, Binding::Key(KeyCode::A)),
+            (Action::MoveLeft, Binding::GamepadAxis(LeftStickX, Negative)),
+            (Action::MoveRight, Binding::Key(KeyCode::D)),
+            (
+                Action::MoveRight,
+                Binding::GamepadAxis(LeftStickX, Positive),
+            ),
+            (Action::RotateLeft, Binding::Key(KeyCode::Q)),
+            (
+                Action::RotateLeft,
+                Binding::GamepadButton(GamepadButtonType::LeftTrigger),
+            ),
+            (Action::RotateRight, Binding::Key(KeyCode::E)),
+            (
+                Action::RotateRight,
+                Binding::GamepadButton(GamepadButtonType::RightTrigger),
+            ),
+            (
+                Action::RotateLeft,
+                Binding::GamepadAxis(RightStickX, Negative),
+            ),
+            (
+                Action::RotateRight,
+                Binding::GamepadAxis(RightStickX, Positive),
+            ),
+            (Action::PitchUp, Binding::Key(KeyCode::R)),
+            (Action::PitchUp, Binding::GamepadAxis(RightStickY, Positive)),
+            (Action::PitchDown, Binding::Key(KeyCode::F)),
+            (
+                Action::PitchDown,
+                Binding::GamepadAxis(RightStickY, Negative),
+            ),
+            (Action::ZoomIn, Binding::Key(KeyCode::Equals)),
+            (
+                Action::ZoomIn,
+                Binding::GamepadButton(GamepadButtonType::DPadUp),
+            ),
+            (Action::ZoomOut, Binding::Key(KeyCode::Minus)),
+            (
+                Action::ZoomOut,
+                Binding::GamepadButton(GamepadButtonType::DPadDown),
+            ),
+            (Action::DragPan, Binding::Mouse(MouseButton::Middle)),
+            (Action::Orbit, Binding::Key(KeyCode::LAlt)),
+            (Action::Focus, Binding::Key(KeyCode::C)),
+            (
+                Action::Focus,
+                Binding::GamepadButton(GamepadButtonType::North),
+            ),
+            (Action::ToggleFollow, Binding::Key(KeyCode::V)),
+            (
+                Action::ToggleFollow,
+                Binding::GamepadButton(GamepadButtonType::RightThumb),
+            ),
+            (Action::Pause, Binding::Key(KeyCode::Space)),
+            (
+                Action::Pause,
+                Binding::GamepadButton(GamepadButtonType::Start),
+            ),
+            (Action::Select, Binding::Mouse(MouseButton::Left)),
+            (
+                Action::Select,
+                Binding::GamepadButton(GamepadButtonType::South),
+            ),
+            (Action::AddToSelection, Binding::Key(KeyCode::LShift)),
+            (Action::SelectAllOfType, Binding::Key(KeyCode::T)),
+            (
+                Action::SelectAllOfType,
+                Binding::GamepadButton(GamepadButtonType::LeftThumb),
+            ),
+            (Action::Cancel, Binding::Key(KeyCode::Escape)),
+            (Action::Cancel, Binding::Mouse(MouseButton::Right)),
+            (
+                Action::Cancel,
+                Binding::GamepadButton(GamepadButtonType::East),
+            ),
+            (Action::Build, Binding::Key(KeyCode::B)),
+            (
+                Action::Build,
+                Binding::GamepadButton(GamepadButtonType::West),
+            ),
+            (Action::ToggleEditor, Binding::Key(KeyCode::F2)),
+            (Action::NextTool, Binding::Key(KeyCode::Tab)),
+            (Action::Undo, Binding::Key(KeyCode::Z)),
+            (Action::Redo, Binding::Key(KeyCode::Y)),
+            (Action::Delete, Binding::Key(KeyCode::Delete)),
+            (Action::Delete, Binding::Key(KeyCode::X)),
+            (Action::Save, Binding::Key(KeyCode::F5)),
+            (Action::ToggleSettings, Binding::Key(KeyCode::F10)),
+        ] {
+            bindings
+                .bind(action, binding)
+                .expect("Default bindings must not conflict");
+        }
+        bindings
+    }
+}
+
+impl InputBindings {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], |b| b.as_slice())
+    }
+
+    // Returns the action currently triggered by `binding`, if any.
+    pub fn action_for(&self, binding: Binding) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    // Adds `binding` to `action`. Fails if the binding already triggers a different action.
+    pub fn bind(&mut self, action: Action, binding: Binding) -> Result<(), BindingConflict> {
+        match self.action_for(binding) {
+            Some(existing) if existing == action => return Ok(()),
+            Some(existing) => return Err(BindingConflict { binding, existing }),
+            None => {}
+        }
+        self.bindings.entry(action).or_default().push(binding);
+        Ok(())
+    }
+
+    // Binds `binding` to `action`, removing it from whichever action previously used it.
+    pub fn rebind(&mut self, action: Action, binding: Binding) -> Option<Action> {
+        let previous = self.action_for(binding).filter(|a| *a != action);
+        if let Some(previous) = previous {
+            self.unbind(previous, binding);
+        }
+        self.bind(action, binding)
+            .expect("Binding was removed from its previous action");
+        previous
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    // Leaves `action` with no bindings. It stays that way when saved and loaded again, rather than
+    // going back to the defaults.
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.insert(action, Vec::new());
+    }
+
+    // Checks that no binding triggers more than one action.
+    pub fn validate(&self) -> Result<(), BindingConflict> {
+        let mut seen = HashMap::default();
+        for (action, bindings) in &self.bindings {
+            for binding in bindings {
+                if let Some(existing) = seen.insert(*binding, *action) {
+                    if existing != *action {
+                        return Err(BindingConflict {
+                            binding: *binding,
+                            existing,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Bindings from the file, with the defaults for actions it doesn't mention, like ones added
+    // since it was saved.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindingsError> {
+        let contents = std::fs::read_to_string(path)?;
+        let saved: InputBindings = ron::from_str(&contents)?;
+        saved.validate()?;
+        let bindings = saved.over_defaults();
+        bindings.validate()?;
+        Ok(bindings)
+    }
+
+    // Adds the default bindings of actions which have none of their own. Defaults which already
+    // trigger another action are left out, since the player chose that.
+    fn over_defaults(mut self) -> Self {
+        for (action, defaults) in InputBindings::default().bindings {
+            if self.bindings.contains_key(&action) {
+                continue;
+            }
+            for binding in defaults {
+                if self.action_for(binding).is_none() {
+                    self.bindings.entry(action).or_default().push(binding);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BindingsError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ActionData {
+    // Strongest input for the action, in [0, 1]. Digital inputs are either 0 or 1.
+    value: f32,
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+// The state of every action this frame. Systems should read this instead of raw input.
+#[derive(Debug, Default, Resource)]
+pub struct ActionState {
+    actions: HashMap<Action, ActionData>,
+}
+
+impl ActionState {
+    fn data(&self, action: Action) -> ActionData {
+        self.actions.get(&action).copied().unwrap_or_default()
+    }
+
+    pub fn value(&self, action: Action) -> f32 {
+        self.data(action).value
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.data(action).pressed
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.data(action).just_pressed
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.data(action).just_released
+    }
+
+    // Value of `positive` minus the value of `negative`, in [-1, 1].
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+
+    fn set(&mut self, action: Action, value: f32) {
+        let data = self.actions.entry(action).or_default();
+        let pressed = value > 0.0;
+        data.just_pressed = pressed && !data.pressed;
+        data.just_released = !pressed && data.pressed;
+        data.pressed = pressed;
+        data.value = value;
+    }
+}
+
+pub struct ActionsPlugin {}
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Action>()
+            .insert_resource(ActionState::default())
+            .add_startup_system(load_bindings.in_base_set(StartupSet::PreStartup))
+            .add_system(
+                update_action_state
+                    .in_base_set(CoreSet::PreUpdate)
+                    .after(InputSystem),
+            )
+            .add_system(save_bindings);
+    }
+}
+
+fn load_bindings(mut commands: Commands) {
+    let bindings = if Path::new(BINDINGS_PATH).exists() {
+        InputBindings::load(BINDINGS_PATH).unwrap_or_else(|err| {
+            warn!("Using default bindings: {err}");
+            InputBindings::default()
+        })
+    } else {
+        InputBindings::default()
+    };
+    commands.insert_resource(bindings);
+}
+
+// Persist bindings whenever they are changed at runtime.
+fn save_bindings(bindings: Res<InputBindings>) {
+    if !bindings.is_changed() || bindings.is_added() {
+        return;
+    }
+    if let Err(err) = bindings.save(BINDINGS_PATH) {
+        error!("{err}");
+    }
+}
+
+fn binding_value(
+    binding: Binding,
+    keyboard: &Input<KeyCode>,
+    mouse: &Input<MouseButton>,
+    gamepads: &Gamepads,
+    gamepad_buttons: &Input<GamepadButton>,
+    gamepad_axes: &Axis<GamepadAxis>,
+) -> f32 {
+    let digital = |pressed: bool| if pressed { 1.0 } else { 0.0 };
+    match binding {
+        Binding::Key(key) => digital(keyboard.pressed(key)),
+        Binding::Mouse(button) => digital(mouse.pressed(button)),
+        Binding::GamepadButton(button_type) => digital(
+            gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, button_type))),
+        ),
+        Binding::GamepadAxis(axis_type, direction) => gamepads
+            .iter()
+            .filter_map(|gamepad| gamepad_axes.get(GamepadAxis::new(gamepad, axis_type)))
+            .map(|val| match direction {
+                AxisDirection::Positive => val,
+                AxisDirection::Negative => -val,
+            })
+            .filter(|val| *val > AXIS_DEADZONE)
+            .fold(0.0, f32::max),
+    }
+}
+
+fn update_action_state(
+    bindings: Res<InputBindings>,
+    mut action_state: ResMut<ActionState>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+) {
+    for action in Action::ALL {
+        let value = bindings
+            .bindings(action)
+            .iter()
+            .map(|binding| {
+                binding_value(
+                    *binding,
+                    &keyboard,
+                    &mouse,
+                    &gamepads,
+                    &gamepad_buttons,
+                    &gamepad_axes,
+                )
+            })
+            .fold(0.0, f32::max);
+        action_state.set(action, value.min(1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::reflect::{TypeInfo, Typed};
+    use std::collections::BTreeSet;
+
+    const W: Binding = Binding::Key(KeyCode::W);
+
+    #[test]
+    fn binding_an_input_used_by_another_action_conflicts() {
+        let mut bindings = InputBindings::default();
+        assert_eq!(
+            bindings.bind(Action::Pause, W),
+            Err(BindingConflict {
+                binding: W,
+                existing: Action::MoveForward,
+            })
+        );
+        // Binding it again to the same action is fine.
+        assert_eq!(bindings.bind(Action::MoveForward, W), Ok(()));
+        assert_eq!(bindings.action_for(W), Some(Action::MoveForward));
+    }
+
+    #[test]
+    fn rebinding_moves_the_input_to_the_new_action() {
+        let mut bindings = InputBindings::default();
+        assert_eq!(bindings.rebind(Action::Pause, W), Some(Action::MoveForward));
+        assert_eq!(bindings.action_for(W), Some(Action::Pause));
+        assert!(!bindings.bindings(Action::MoveForward).contains(&W));
+        assert_eq!(bindings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_finds_inputs_bound_twice() {
+        let mut bindings = InputBindings::default();
+        assert_eq!(bindings.validate(), Ok(()));
+        // Files can contain conflicts which `bind` would refuse.
+        bindings.bindings.entry(Action::Pause).or_default().push(W);
+        assert_eq!(bindings.validate().map_err(|err| err.binding), Err(W));
+    }
+
+    #[test]
+    fn bindings_survive_saving_and_loading() {
+        let path = std::env::temp_dir()
+            .join(format!("bevy-tutorial-{}", std::process::id()))
+            .join("bindings.ron");
+        let mut bindings = InputBindings::default();
+        bindings.rebind(Action::Pause, W);
+        bindings.save(&path).unwrap();
+        let loaded = InputBindings::load(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded.unwrap(), bindings);
+    }
+
+    #[test]
+    fn every_action_is_listed_and_bound_by_default() {
+        let TypeInfo::Enum(info) = Action::type_info() else {
+            panic!("Action should be an enum");
+        };
+        let listed: BTreeSet<Action> = Action::ALL.into_iter().collect();
+        assert_eq!(listed.len(), info.variant_len());
+        let bindings = InputBindings::default();
+        for action in Action::ALL {
+            assert!(!bindings.bindings(action).is_empty(), "{action:?}");
+        }
+    }
+
+    #[test]
+    fn actions_missing_from_a_saved_file_get_their_defaults() {
+        let path = std::env::temp_dir()
+            .join(format!("bevy-tutorial-old-{}", std::process::id()))
+            .join("bindings.ron");
+        // A file from before the settings menu, where F10 was used for pausing.
+        let mut old = InputBindings::default();
+        old.bindings.remove(&Action::ToggleSettings);
+        old.rebind(Action::Pause, Binding::Key(KeyCode::F10));
+        old.clear(Action::Save);
+        old.save(&path).unwrap();
+        let loaded = InputBindings::load(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            loaded.action_for(Binding::Key(KeyCode::F10)),
+            Some(Action::Pause)
+        );
+        assert!(loaded.bindings(Action::ToggleSettings).is_empty());
+        assert!(loaded.bindings(Action::Save).is_empty());
+        let defaults = InputBindings::default();
+        assert_eq!(
+            loaded.bindings(Action::Undo),
+            defaults.bindings(Action::Undo)
+        );
+        assert_eq!(loaded.validate(), Ok(()));
+    }
+}
@@ -1,13 +1,19 @@
+use crate::collision::*;
 use crate::components::*;
+use crate::health::*;
 use crate::target::*;
 
 use bevy::prelude::*;
-use bevy::utils::FloatOrd;
+use bevy_rapier3d::prelude::{Collider, RapierContext};
+
+pub const BULLET_RADIUS: f32 = 0.07;
 
 #[derive(Debug, Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct Bullet {
     pub damage: f32,
+    // The tower which fired the bullet.
+    pub source: Option<Entity>,
 }
 
 #[derive(Debug, Bundle, Default)]
@@ -17,10 +23,13 @@ pub struct BulletBundle {
 }
 
 impl BulletBundle {
-    pub fn new(velocity: Vec3, damage: f32) -> Self {
+    pub fn new(velocity: Vec3, damage: f32, source: Entity) -> Self {
         Self {
             velocity: Velocity { val: velocity },
-            bullet: Bullet { damage },
+            bullet: Bullet {
+                damage,
+                source: Some(source),
+            },
         }
     }
 }
@@ -33,6 +42,7 @@ impl Plugin for BulletPlugin {
     }
 }
 
+// Bullets hit the first live target whose hitbox they overlap.
 fn update_bullets(
     mut commands: Commands,
     mut bullets: Query<(
@@ -43,9 +53,21 @@ fn update_bullets(
         &GlobalTransform,
         &Bullet,
     )>,
-    mut targets: Query<(&mut Health, &GlobalTransform, &Target)>,
+    targets: Query<(), (With<Target>, Without<Dead>)>,
+    parent_query: Query<&Parent>,
+    rapier_context: Res<RapierContext>,
+    mut damage_events: EventWriter<DamageEvent>,
     time: Res<Time>,
 ) {
+    let shape = Collider::ball(BULLET_RADIUS);
+    // Hitboxes are children of the target they belong to.
+    let target_of = |collider| {
+        parent_query
+            .get(collider)
+            .ok()
+            .map(|parent| parent.get())
+            .filter(|target| targets.contains(*target))
+    };
     for (entity, velocity, mut transform, mut lifetime, global_transform, bullet) in &mut bullets {
         lifetime.timer.tick(time.delta());
 
@@ -57,25 +79,97 @@ fn update_bullets(
 
         transform.translation += velocity.val * time.delta_seconds();
 
-        let closest_target =
-            targets
-                .iter_mut()
-                .min_by_key(|(_health, target_transform, _target)| {
-                    FloatOrd(
-                        global_transform
-                            .translation()
-                            .distance(target_transform.translation()),
-                    )
-                });
-
-        let (mut target_health, target_location, hitbox) = match closest_target {
-            None => continue,
-            Some((health, transform, target)) => (health, transform.translation(), target.hitbox),
+        let is_live_target = |collider| target_of(collider).is_some();
+        let filter = filter_for(TARGETS).predicate(&is_live_target);
+        let Some(target) = rapier_context
+            .intersection_with_shape(
+                global_transform.translation(),
+                Quat::IDENTITY,
+                &shape,
+                filter,
+            )
+            .and_then(target_of)
+        else {
+            continue;
         };
-        let distance = global_transform.translation().distance(target_location);
-        if distance < hitbox {
-            target_health.val -= bullet.damage;
-            commands.entity(entity).despawn_recursive();
-        }
+        damage_events.send(DamageEvent {
+            target,
+            amount: bullet.damage,
+            source: bullet.source,
+        });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    use bevy_rapier3d::prelude::Group;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::with_physics();
+        app.add_plugin(HealthPlugin {}).add_plugin(BulletPlugin {});
+        app
+    }
+
+    // An entity with a hitbox child in `groups`, like towers and targets.
+    fn spawn_with_hitbox(app: &mut App, position: Vec3, groups: Group) -> Entity {
+        app.world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(position)),
+                Health::new(10.0),
+            ))
+            .with_children(|children| {
+                children.spawn((
+                    TransformBundle::default(),
+                    Collider::ball(0.2),
+                    member_of(groups),
+                ));
+            })
+            .id()
+    }
+
+    fn spawn_bullet(app: &mut App, position: Vec3) -> Entity {
+        // Placed in the world right away, as if its transform had been propagated.
+        let transform = Transform::from_translation(position);
+        app.world
+            .spawn((
+                TransformBundle {
+                    local: transform,
+                    global: transform.into(),
+                },
+                BulletBundle {
+                    bullet: Bullet {
+                        damage: 3.0,
+                        source: None,
+                    },
+                    ..default()
+                },
+                Lifetime {
+                    timer: Timer::from_seconds(10.0, TimerMode::Once),
+                },
+            ))
+            .id()
+    }
+
+    #[test]
+    fn bullets_only_hit_targets() {
+        let mut app = app();
+        let target = spawn_with_hitbox(&mut app, Vec3::ZERO, TARGETS | PICKABLE);
+        app.world.entity_mut(target).insert(Target { hitbox: 0.2 });
+        let tower = spawn_with_hitbox(&mut app, Vec3::X * 5.0, TOWERS | PICKABLE);
+        // Let the physics pick up the hitboxes.
+        app.update();
+
+        let hits = spawn_bullet(&mut app, Vec3::new(0.1, 0.0, 0.0));
+        let misses = spawn_bullet(&mut app, Vec3::X * 5.0);
+        app.run(3);
+
+        assert!(app.world.get_entity(hits).is_none());
+        assert!(app.world.get_entity(misses).is_some());
+        assert_eq!(app.world.get::<Health>(target).unwrap().val, 7.0);
+        assert_eq!(app.world.get::<Health>(tower).unwrap().val, 10.0);
     }
 }
@@ -0,0 +1,419 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::actions::*;
+use crate::hud::*;
+
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+// RTS style camera which orbits a focus point on the ground.
+//
+// Input edits the desired focus/distance/yaw/pitch, and the transform eases towards them.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct RtsCamera {
+    pub focus: Vec3,
+    pub distance: f32,
+    // Rotation around the Y axis. 0 places the camera on the +Z side of the focus.
+    pub yaw: f32,
+    // Angle above the ground plane.
+    pub pitch: f32,
+    // The values currently applied to the transform.
+    pub current_focus: Vec3,
+    pub current_distance: f32,
+    pub current_yaw: f32,
+    pub current_pitch: f32,
+    // Entity whose position the focus tracks every frame.
+    pub follow: Option<Entity>,
+}
+
+impl RtsCamera {
+    // Creates a camera placed at `focus + offset`, looking at `focus`.
+    pub fn from_offset(focus: Vec3, offset: Vec3) -> Self {
+        let distance = offset.length();
+        let yaw = offset.x.atan2(offset.z);
+        let pitch = (offset.y / distance).asin();
+        Self {
+            focus,
+            distance,
+            yaw,
+            pitch,
+            current_focus: focus,
+            current_distance: distance,
+            current_yaw: yaw,
+            current_pitch: pitch,
+            follow: None,
+        }
+    }
+
+    // Moves the focus to `point`, zooming in or out to `distance`.
+    pub fn focus_on(&mut self, point: Vec3, distance: f32) {
+        self.focus = point;
+        self.distance = distance;
+    }
+
+    // Direction along the ground that the camera is facing.
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(-self.yaw.sin(), 0.0, -self.yaw.cos())
+    }
+
+    pub fn left(&self) -> Vec3 {
+        Vec3::new(-self.yaw.cos(), 0.0, self.yaw.sin())
+    }
+
+    fn transform(&self) -> Transform {
+        let offset = Vec3::new(
+            self.current_pitch.cos() * self.current_yaw.sin(),
+            self.current_pitch.sin(),
+            self.current_pitch.cos() * self.current_yaw.cos(),
+        ) * self.current_distance;
+        Transform::from_translation(self.current_focus + offset)
+            .looking_at(self.current_focus, Vec3::Y)
+    }
+}
+
+#[derive(Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct RtsCameraSettings {
+    // Units per second when at a distance of 1. Panning scales with zoom.
+    pub pan_speed: f32,
+    // Radians per second.
+    pub rotate_speed: f32,
+    pub pitch_speed: f32,
+    // Fraction of the distance zoomed per scroll line.
+    pub zoom_speed: f32,
+    // Radians per pixel of mouse movement while orbiting.
+    pub orbit_sensitivity: f32,
+    // Pan when the cursor is within this many pixels of the window edge.
+    pub edge_pan_margin: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    // The focus point is kept within these bounds on the XZ plane.
+    pub bounds_min: Vec2,
+    pub bounds_max: Vec2,
+    // Higher values catch up to the desired state faster.
+    pub smoothing: f32,
+    // Distance used to frame an entity when focusing on it.
+    pub focus_distance: f32,
+}
+
+impl Default for RtsCameraSettings {
+    fn default() -> Self {
+        Self {
+            pan_speed: 0.8,
+            rotate_speed: 1.0,
+            pitch_speed: 0.8,
+            zoom_speed: 0.1,
+            orbit_sensitivity: 0.005,
+            edge_pan_margin: 10.0,
+            min_distance: 2.0,
+            max_distance: 20.0,
+            min_pitch: 0.2,
+            max_pitch: FRAC_PI_2 - 0.05,
+            bounds_min: Vec2::splat(-3.0),
+            bounds_max: Vec2::splat(3.0),
+            smoothing: 10.0,
+            focus_distance: 4.0,
+        }
+    }
+}
+
+impl RtsCameraSettings {
+    // Focus point and distance which keep all of `points` in view, or `None` if there are none.
+    pub fn framing(&self, points: &[Vec3]) -> Option<(Vec3, f32)> {
+        if points.is_empty() {
+            return None;
+        }
+        let center = points.iter().sum::<Vec3>() / points.len() as f32;
+        let radius = points
+            .iter()
+            .map(|point| point.distance(center))
+            .fold(0.0, f32::max);
+        let distance = self.focus_distance + radius * 2.0;
+        Some((center, distance.clamp(self.min_distance, self.max_distance)))
+    }
+
+    pub fn clamp_to_bounds(&self, point: Vec3) -> Vec3 {
+        Vec3::new(
+            point.x.clamp(self.bounds_min.x, self.bounds_max.x),
+            point.y,
+            point.z.clamp(self.bounds_min.y, self.bounds_max.y),
+        )
+    }
+}
+
+// Where a ray hits the horizontal plane at `height`, if it points towards it.
+pub fn intersect_plane(ray: Ray, height: f32) -> Option<Vec3> {
+    let distance = (height - ray.origin.y) / ray.direction.y;
+    (distance.is_finite() && distance > 0.0).then(|| ray.get_point(distance))
+}
+
+pub struct RtsCameraPlugin {}
+
+impl Plugin for RtsCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<RtsCamera>()
+            .register_type::<RtsCameraSettings>()
+            .insert_resource(RtsCameraSettings::default())
+            .add_startup_system(spawn_camera)
+            .add_systems((camera_control, follow_entity, apply_camera_smoothing).chain());
+    }
+}
+
+fn spawn_camera(mut commands: Commands) {
+    let camera = RtsCamera::from_offset(Vec3::ZERO, Vec3::new(-2.0, 2.5, 5.0));
+    commands
+        .spawn(Camera3dBundle {
+            transform: camera.transform(),
+            ..default()
+        })
+        .insert(camera)
+        .insert(Name::new("Camera"));
+}
+
+fn camera_control(
+    actions: Res<ActionState>,
+    settings: Res<RtsCameraSettings>,
+    pointer_over_ui: Res<PointerOverUi>,
+    primary_query: Query<&Window, With<PrimaryWindow>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut camera_query: Query<&mut RtsCamera>,
+    time: Res<Time>,
+) {
+    let Ok(mut camera) = camera_query.get_single_mut() else {
+        return;
+    };
+    // Use raw time so that we can pause time and still move the camera.
+    let dt = time.raw_delta_seconds();
+
+    // Pan faster when zoomed out so the on screen speed feels constant.
+    let pan_speed = settings.pan_speed * camera.distance;
+    let mut pan = Vec2::new(
+        actions.axis(Action::MoveLeft, Action::MoveRight),
+        actions.axis(Action::MoveForward, Action::MoveBack),
+    );
+
+    // Not while using the UI along the edges of the screen.
+    if let Some((window, cursor)) = primary_query
+        .get_single()
+        .ok()
+        .filter(|_| !pointer_over_ui.0)
+        .and_then(|window| window.cursor_position().map(|cursor| (window, cursor)))
+    {
+        // Cursor position is relative to the bottom left of the window.
+        let margin = settings.edge_pan_margin;
+        if cursor.x < margin {
+            pan.x += 1.0;
+        } else if cursor.x > window.width() - margin {
+            pan.x -= 1.0;
+        }
+        if cursor.y < margin {
+            pan.y -= 1.0;
+        } else if cursor.y > window.height() - margin {
+            pan.y += 1.0;
+        }
+    }
+    let (forward, left) = (camera.forward(), camera.left());
+    camera.focus += (left * pan.x + forward * pan.y) * pan_speed * dt;
+
+    // Mouse drag either pans or orbits depending on the modifier.
+    let drag: Vec2 = mouse_motion.iter().map(|motion| motion.delta).sum();
+    let mut drag_pan = Vec2::ZERO;
+    if actions.pressed(Action::DragPan) {
+        if actions.pressed(Action::Orbit) {
+            camera.yaw -= drag.x * settings.orbit_sensitivity;
+            camera.pitch += drag.y * settings.orbit_sensitivity;
+        } else {
+            drag_pan = drag;
+            // Scale so that the ground roughly sticks to the cursor.
+            let drag_speed = camera.distance * 0.002;
+            camera.focus += (left * drag.x + forward * drag.y) * drag_speed;
+        }
+    }
+
+    // Manually panning takes control back from follow mode.
+    if pan != Vec2::ZERO || drag_pan != Vec2::ZERO {
+        camera.follow = None;
+    }
+
+    camera.yaw +=
+        actions.axis(Action::RotateLeft, Action::RotateRight) * settings.rotate_speed * dt;
+    camera.pitch += actions.axis(Action::PitchUp, Action::PitchDown) * settings.pitch_speed * dt;
+
+    let scroll: f32 = mouse_wheel
+        .iter()
+        .map(|wheel| match wheel.unit {
+            MouseScrollUnit::Line => wheel.y,
+            MouseScrollUnit::Pixel => wheel.y / 100.0,
+        })
+        .sum::<f32>()
+        + actions.axis(Action::ZoomIn, Action::ZoomOut) * 10.0 * dt;
+    camera.distance *= (1.0 - settings.zoom_speed).powf(scroll);
+
+    camera.distance = camera
+        .distance
+        .clamp(settings.min_distance, settings.max_distance);
+    camera.pitch = camera.pitch.clamp(settings.min_pitch, settings.max_pitch);
+    camera.focus = settings.clamp_to_bounds(camera.focus);
+}
+
+fn follow_entity(
+    settings: Res<RtsCameraSettings>,
+    mut camera_query: Query<&mut RtsCamera>,
+    transforms: Query<&GlobalTransform>,
+) {
+    for mut camera in &mut camera_query {
+        let Some(entity) = camera.follow else {
+            continue;
+        };
+        let Ok(transform) = transforms.get(entity) else {
+            // The entity was despawned.
+            camera.follow = None;
+            continue;
+        };
+        camera.focus = settings.clamp_to_bounds(transform.translation());
+    }
+}
+
+fn apply_camera_smoothing(
+    settings: Res<RtsCameraSettings>,
+    mut camera_query: Query<(&mut Transform, &mut RtsCamera)>,
+    time: Res<Time>,
+) {
+    // Frame rate independent exponential smoothing.
+    let t = 1.0 - (-settings.smoothing * time.raw_delta_seconds()).exp();
+    for (mut transform, mut camera) in &mut camera_query {
+        camera.current_focus = camera.current_focus.lerp(camera.focus, t);
+        camera.current_distance += (camera.distance - camera.current_distance) * t;
+        camera.current_yaw += (camera.yaw - camera.current_yaw) * t;
+        camera.current_pitch += (camera.pitch - camera.current_pitch) * t;
+        *transform = camera.transform();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::default();
+        app.init_resource::<ActionState>()
+            .init_resource::<PointerOverUi>()
+            .insert_resource(RtsCameraSettings::default())
+            .add_event::<MouseMotion>()
+            .add_event::<MouseWheel>()
+            .add_systems((camera_control, apply_camera_smoothing).chain());
+        app
+    }
+
+    fn scroll(app: &mut TestApp, lines: f32) {
+        app.world.send_event(MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: 0.0,
+            y: lines,
+        });
+        app.update();
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        let mut app = app();
+        let camera = app
+            .world
+            .spawn((
+                Transform::default(),
+                RtsCamera::from_offset(Vec3::ZERO, Vec3::new(0.0, 3.0, 4.0)),
+            ))
+            .id();
+        let settings = RtsCameraSettings::default();
+
+        scroll(&mut app, -100.0);
+        let distance = app.world.get::<RtsCamera>(camera).unwrap().distance;
+        assert_eq!(distance, settings.max_distance);
+        scroll(&mut app, 100.0);
+        let distance = app.world.get::<RtsCamera>(camera).unwrap().distance;
+        assert_eq!(distance, settings.min_distance);
+    }
+
+    #[test]
+    fn smoothing_converges_on_the_desired_state() {
+        let mut app = app();
+        let mut rts_camera = RtsCamera::from_offset(Vec3::ZERO, Vec3::new(0.0, 3.0, 4.0));
+        rts_camera.focus_on(Vec3::new(1.0, 0.0, -1.0), 8.0);
+        let camera = app.world.spawn((Transform::default(), rts_camera)).id();
+
+        // The clock only starts ticking on the second update.
+        app.run(2);
+        let rts_camera = app.world.get::<RtsCamera>(camera).unwrap();
+        let first_step = rts_camera.current_distance;
+        assert!(first_step > 5.0 && first_step < 8.0);
+
+        app.run(40);
+        let rts_camera = app.world.get::<RtsCamera>(camera).unwrap();
+        assert!((rts_camera.current_distance - 8.0).abs() < 1e-3);
+        assert!(rts_camera.current_focus.distance(rts_camera.focus) < 1e-3);
+        let transform = app.world.get::<Transform>(camera).unwrap();
+        let expected = rts_camera.transform();
+        assert!(transform.translation.distance(expected.translation) < 1e-6);
+    }
+
+    #[test]
+    fn focusing_from_close_in_backs_off() {
+        let mut app = app();
+        let settings = RtsCameraSettings::default();
+        let mut rts_camera = RtsCamera::from_offset(Vec3::ZERO, Vec3::new(0.0, 1.2, 1.6));
+        assert_eq!(rts_camera.distance, 2.0);
+        let (center, distance) = settings.framing(&[Vec3::X]).unwrap();
+        assert_eq!((center, distance), (Vec3::X, 4.0));
+        rts_camera.focus_on(center, distance);
+        let camera = app.world.spawn((Transform::default(), rts_camera)).id();
+
+        app.run(40);
+        let rts_camera = app.world.get::<RtsCamera>(camera).unwrap();
+        assert_eq!(rts_camera.distance, 4.0);
+        let transform = app.world.get::<Transform>(camera).unwrap();
+        assert!((transform.translation.distance(Vec3::X) - 4.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn wide_selections_are_framed_from_further_away() {
+        let settings = RtsCameraSettings::default();
+        // 4 to see a single point, plus twice the radius of the selection.
+        let (center, distance) = settings
+            .framing(&[Vec3::new(-3.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0)])
+            .unwrap();
+        assert_eq!((center, distance), (Vec3::ZERO, 10.0));
+        let (_, distance) = settings
+            .framing(&[Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0)])
+            .unwrap();
+        assert!((distance - (4.0 + 2.0 * 2f32.sqrt())).abs() < 1e-5);
+        // No further than the camera can zoom out.
+        let (_, distance) = settings
+            .framing(&[Vec3::new(-30.0, 0.0, 0.0), Vec3::new(30.0, 0.0, 0.0)])
+            .unwrap();
+        assert_eq!(distance, 20.0);
+        assert!(settings.framing(&[]).is_none());
+    }
+
+    #[test]
+    fn edge_pan_stops_over_the_ui() {
+        for (over_ui, pans) in [(false, true), (true, false)] {
+            let mut app = app();
+            app.insert_resource(PointerOverUi(over_ui));
+            let mut window = Window::default();
+            window.set_cursor_position(Some(Vec2::new(1.0, 300.0)));
+            app.world.spawn((window, PrimaryWindow));
+            let rts_camera = RtsCamera::from_offset(Vec3::ZERO, Vec3::new(0.0, 3.0, 4.0));
+            let camera = app.world.spawn((Transform::default(), rts_camera)).id();
+
+            app.run(3);
+            let focus = app.world.get::<RtsCamera>(camera).unwrap().focus;
+            assert_eq!(focus != Vec3::ZERO, pans, "over UI: {over_ui}");
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use bevy_rapier3d::prelude::*;
+
+// Collision groups. Every collider is a member of the group for the kind of thing it belongs to,
+// plus `PICKABLE` if the player can point at it. Queries say which groups they care about with
+// `filter_for`, rather than checking what they hit afterwards.
+pub const GROUND: Group = Group::GROUP_1;
+pub const TOWERS: Group = Group::GROUP_2;
+pub const TARGETS: Group = Group::GROUP_3;
+pub const PROJECTILES: Group = Group::GROUP_4;
+// Level decoration.
+pub const PROPS: Group = Group::GROUP_5;
+// Things which can be hovered and selected with the cursor.
+pub const PICKABLE: Group = Group::GROUP_6;
+
+// What towers can't see through. Targets don't hide each other.
+pub const BLOCKS_SIGHT: Group = GROUND.union(TOWERS).union(PROPS);
+
+// What the cursor's ray stops at. Bullets, which are tiny and everywhere, are left out.
+pub const BLOCKS_CURSOR: Group = GROUND.union(PROPS).union(PICKABLE);
+
+// Groups for a collider which is a member of `groups`, and may interact with anything.
+pub fn member_of(groups: Group) -> CollisionGroups {
+    CollisionGroups::new(groups, Group::ALL)
+}
+
+// Query filter which only finds colliders in any of `groups`.
+pub fn filter_for<'a>(groups: Group) -> QueryFilter<'a> {
+    QueryFilter::new().groups(CollisionGroups::new(Group::ALL, groups))
+}
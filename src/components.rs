@@ -7,12 +7,6 @@ pub struct Lifetime {
     pub timer: Timer,
 }
 
-#[derive(Debug, Component, Default, Reflect)]
-#[reflect(Component)]
-pub struct Health {
-    pub val: f32,
-}
-
 #[derive(Debug, Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct Velocity {
@@ -23,8 +17,6 @@ pub struct ComponentsPlugin {}
 
 impl Plugin for ComponentsPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<Lifetime>()
-            .register_type::<Health>()
-            .register_type::<Velocity>();
+        app.register_type::<Lifetime>().register_type::<Velocity>();
     }
 }
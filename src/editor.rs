@@ -0,0 +1,636 @@
+use crate::actions::*;
+use crate::enemies::*;
+use crate::hud::*;
+use crate::level::*;
+use crate::picking::*;
+use crate::resources::*;
+use crate::terrain::*;
+
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+
+// How close, on the ground, the cursor has to be to a handle to grab it.
+pub const PICK_RADIUS: f32 = 0.2;
+// Edits which can be undone.
+pub const MAX_HISTORY: usize = 100;
+// Scene placed by the prop tool, relative to the assets folder, and its hitbox.
+pub const PROP_MODEL: &str = "Tomato.glb#Scene0";
+pub const PROP_COLLIDER: ColliderShape = ColliderShape::Ball { radius: 0.1 };
+
+const HANDLE_SIZE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditorTool {
+    // Drag the spawn or goal. Clicking elsewhere moves the spawn.
+    #[default]
+    Spawn,
+    GroundPath,
+    AirPath,
+    // Drag out rectangles where towers may be built.
+    Buildable,
+    Props,
+}
+
+impl EditorTool {
+    pub fn next(self) -> Self {
+        match self {
+            EditorTool::Spawn => EditorTool::GroundPath,
+            EditorTool::GroundPath => EditorTool::AirPath,
+            EditorTool::AirPath => EditorTool::Buildable,
+            EditorTool::Buildable => EditorTool::Props,
+            EditorTool::Props => EditorTool::Spawn,
+        }
+    }
+}
+
+// Part of the level which can be grabbed and moved in the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditHandle {
+    Spawn,
+    Goal,
+    Waypoint(PathLayer, usize),
+    // Moves the whole area.
+    Area(usize),
+    // The `max` corner, which resizes the area.
+    AreaCorner(usize),
+    Prop(usize),
+}
+
+impl LevelAsset {
+    pub fn handle_position(&self, handle: EditHandle) -> Option<Vec3> {
+        match handle {
+            EditHandle::Spawn => Some(self.route.spawn),
+            EditHandle::Goal => Some(self.route.goal),
+            EditHandle::Waypoint(layer, i) => self.route.path(layer).get(i).copied(),
+            EditHandle::Area(i) => self.buildable.get(i).map(|area| {
+                let center = (area.min + area.max) / 2.0;
+                Vec3::new(center.x, 0.0, center.y)
+            }),
+            EditHandle::AreaCorner(i) => self
+                .buildable
+                .get(i)
+                .map(|area| Vec3::new(area.max.x, 0.0, area.max.y)),
+            EditHandle::Prop(i) => self.props.get(i).map(|prop| prop.position),
+        }
+    }
+
+    // Handles which `tool` can grab.
+    fn handles(&self, tool: EditorTool) -> Vec<EditHandle> {
+        match tool {
+            EditorTool::Spawn => vec![EditHandle::Spawn, EditHandle::Goal],
+            EditorTool::GroundPath => (0..self.route.ground_path.len())
+                .map(|i| EditHandle::Waypoint(PathLayer::Ground, i))
+                .collect(),
+            EditorTool::AirPath => (0..self.route.air_path.len())
+                .map(|i| EditHandle::Waypoint(PathLayer::Air, i))
+                .collect(),
+            EditorTool::Buildable => (0..self.buildable.len())
+                .map(EditHandle::AreaCorner)
+                .collect(),
+            EditorTool::Props => (0..self.props.len()).map(EditHandle::Prop).collect(),
+        }
+    }
+
+    // The handle for `tool` under `point`, preferring the closest. Areas can also be grabbed
+    // anywhere inside them.
+    pub fn handle_at(&self, tool: EditorTool, point: Vec3) -> Option<EditHandle> {
+        let closest = self
+            .handles(tool)
+            .into_iter()
+            .filter_map(|handle| {
+                let distance = (self.handle_position(handle)? - point).xz().length();
+                (distance <= PICK_RADIUS).then_some((handle, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(handle, _)| handle);
+        if closest.is_some() || tool != EditorTool::Buildable {
+            return closest;
+        }
+        // The last area is drawn on top, so pick it first.
+        (0..self.buildable.len())
+            .rev()
+            .find(|i| self.buildable[*i].contains(point))
+            .map(EditHandle::Area)
+    }
+
+    // Moves `handle` to `position` on the ground. Route points keep their height.
+    pub fn move_handle(&mut self, handle: EditHandle, position: Vec3) {
+        let keep_height = |point: &mut Vec3| {
+            point.x = position.x;
+            point.z = position.z;
+        };
+        match handle {
+            EditHandle::Spawn => keep_height(&mut self.route.spawn),
+            EditHandle::Goal => keep_height(&mut self.route.goal),
+            EditHandle::Waypoint(layer, i) => {
+                if let Some(waypoint) = self.route.path_mut(layer).get_mut(i) {
+                    keep_height(waypoint);
+                }
+            }
+            EditHandle::Area(i) => {
+                if let Some(area) = self.buildable.get_mut(i) {
+                    let offset = position.xz() - (area.min + area.max) / 2.0;
+                    area.min += offset;
+                    area.max += offset;
+                }
+            }
+            EditHandle::AreaCorner(i) => {
+                if let Some(area) = self.buildable.get_mut(i) {
+                    area.max = position.xz();
+                }
+            }
+            EditHandle::Prop(i) => {
+                if let Some(prop) = self.props.get_mut(i) {
+                    prop.position = position;
+                }
+            }
+        }
+    }
+
+    // Returns whether there was anything to delete. The spawn and goal can only be moved.
+    pub fn delete(&mut self, handle: EditHandle) -> bool {
+        fn remove<T>(items: &mut Vec<T>, i: usize) -> bool {
+            (i < items.len()).then(|| items.remove(i)).is_some()
+        }
+        match handle {
+            EditHandle::Spawn | EditHandle::Goal => false,
+            EditHandle::Waypoint(layer, i) => remove(self.route.path_mut(layer), i),
+            EditHandle::Area(i) | EditHandle::AreaCorner(i) => remove(&mut self.buildable, i),
+            EditHandle::Prop(i) => remove(&mut self.props, i),
+        }
+    }
+
+    // Adds a waypoint at `position`, between whichever 2 points of the path it lengthens the
+    // path the least.
+    pub fn insert_waypoint(&mut self, layer: PathLayer, position: Vec3) -> usize {
+        let (spawn, goal) = (self.route.spawn, self.route.goal);
+        let path = self.route.path_mut(layer);
+        let points: Vec<Vec3> = [spawn]
+            .into_iter()
+            .chain(path.iter().copied())
+            .chain([goal])
+            .collect();
+        let (index, from) = points
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let detour = (position - pair[0]).xz().length()
+                    + (pair[1] - position).xz().length()
+                    - (pair[1] - pair[0]).xz().length();
+                (i, pair[0], detour)
+            })
+            .min_by(|(.., a), (.., b)| a.total_cmp(b))
+            .map(|(i, from, _)| (i, from))
+            .unwrap_or((0, spawn));
+        path.insert(index, Vec3::new(position.x, from.y, position.z));
+        index
+    }
+
+    // Adds whatever `tool` places at `position`, returning the handle to drag it by.
+    pub fn place(&mut self, tool: EditorTool, position: Vec3) -> EditHandle {
+        match tool {
+            EditorTool::Spawn => {
+                self.move_handle(EditHandle::Spawn, position);
+                EditHandle::Spawn
+            }
+            EditorTool::GroundPath => EditHandle::Waypoint(
+                PathLayer::Ground,
+                self.insert_waypoint(PathLayer::Ground, position),
+            ),
+            EditorTool::AirPath => EditHandle::Waypoint(
+                PathLayer::Air,
+                self.insert_waypoint(PathLayer::Air, position),
+            ),
+            EditorTool::Buildable => {
+                self.buildable.push(BuildableArea {
+                    min: position.xz(),
+                    max: position.xz(),
+                });
+                EditHandle::AreaCorner(self.buildable.len() - 1)
+            }
+            EditorTool::Props => {
+                self.props.push(PropDefinition {
+                    model: PROP_MODEL.to_string(),
+                    position,
+                    rotation: 0.0,
+                    scale: 1.0,
+                    collider: Some(PROP_COLLIDER),
+                });
+                EditHandle::Prop(self.props.len() - 1)
+            }
+        }
+    }
+
+    // Tidies up after a drag: areas dragged inside out are flipped back, and areas with no size
+    // are dropped.
+    pub fn finish_edit(&mut self) {
+        for area in &mut self.buildable {
+            *area = area.normalized();
+        }
+        self.buildable.retain(|area| area.min.cmplt(area.max).all());
+    }
+}
+
+// Snapshots of the level from before each edit.
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    undo: Vec<LevelAsset>,
+    redo: Vec<LevelAsset>,
+}
+
+impl EditHistory {
+    // Call with the level as it was before an edit. Making a new edit drops anything undone.
+    pub fn record(&mut self, before: LevelAsset) {
+        if self.undo.len() >= MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    // Forgets the latest snapshot if the edit didn't end up changing anything.
+    pub fn discard_if_unchanged(&mut self, level: &LevelAsset) {
+        if self.undo.last() == Some(level) {
+            self.undo.pop();
+        }
+    }
+
+    // Returns whether there was anything to undo.
+    pub fn undo(&mut self, level: &mut LevelAsset) -> bool {
+        let Some(previous) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(std::mem::replace(level, previous));
+        true
+    }
+
+    pub fn redo(&mut self, level: &mut LevelAsset) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(std::mem::replace(level, next));
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    handle: EditHandle,
+    // From the cursor to the handle, so that it doesn't jump to the cursor when grabbed.
+    offset: Vec3,
+}
+
+// While enabled, time is paused and clicks edit the level instead of playing.
+#[derive(Debug, Resource, Default)]
+pub struct Editor {
+    pub enabled: bool,
+    pub tool: EditorTool,
+    // Working copy of the level. Written back to the level asset after each edit, which respawns
+    // the level.
+    pub level: Option<LevelAsset>,
+    pub history: EditHistory,
+    drag: Option<Drag>,
+    // Whether time was paused before the editor paused it.
+    was_paused: bool,
+}
+
+// Run condition for systems which only apply while editing.
+pub fn editing(editor: Res<Editor>) -> bool {
+    editor.enabled
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct EditorText;
+
+pub struct EditorPlugin {}
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<EditorText>()
+            .insert_resource(Editor::default())
+            .add_startup_system(spawn_editor_text)
+            .add_system(toggle_editor)
+            .add_systems(
+                (edit_level, draw_editor, update_editor_text)
+                    .after(toggle_editor)
+                    .distributive_run_if(editing),
+            );
+    }
+}
+
+fn toggle_editor(
+    actions: Res<ActionState>,
+    mut editor: ResMut<Editor>,
+    current: Res<CurrentLevel>,
+    levels: Res<Assets<LevelAsset>>,
+    mut time: ResMut<Time>,
+    mut text: Query<&mut Visibility, With<EditorText>>,
+) {
+    if !actions.just_pressed(Action::ToggleEditor) {
+        return;
+    }
+    if editor.enabled {
+        editor.enabled = false;
+        editor.drag = None;
+        if !editor.was_paused {
+            time.unpause();
+        }
+    } else {
+        let Some(level) = levels.get(&current.handle) else {
+            warn!("Can't edit the level until it has loaded");
+            return;
+        };
+        editor.enabled = true;
+        editor.level = Some(level.clone());
+        editor.was_paused = time.is_paused();
+        time.pause();
+    }
+    for mut visibility in &mut text {
+        *visibility = if editor.enabled {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn edit_level(
+    actions: Res<ActionState>,
+    mut editor: ResMut<Editor>,
+    current: Res<CurrentLevel>,
+    mut levels: ResMut<Assets<LevelAsset>>,
+    cursor: Res<CursorWorld>,
+    terrain: Res<Terrain>,
+    pointer_over_ui: Res<PointerOverUi>,
+) {
+    let editor = &mut *editor;
+    let Some(level) = editor.level.as_mut() else {
+        return;
+    };
+    // As a height above the terrain, like the level's positions. Props go on top of whatever the
+    // cursor points at.
+    let point = cursor
+        .position()
+        .map(|point| Vec3::new(point.x, terrain.clearance(point), point.z));
+    let mut committed = false;
+
+    if actions.just_pressed(Action::NextTool) {
+        editor.tool = editor.tool.next();
+        editor.drag = None;
+    }
+
+    if let Some(point) = point.filter(|_| !pointer_over_ui.0) {
+        if actions.just_pressed(Action::Select) {
+            editor.history.record(level.clone());
+            let handle = match level.handle_at(editor.tool, point) {
+                Some(handle) => handle,
+                None => level.place(editor.tool, point),
+            };
+            let offset = level.handle_position(handle).unwrap_or(point) - point;
+            editor.drag = Some(Drag { handle, offset });
+        } else if let (Some(drag), true) = (editor.drag, actions.pressed(Action::Select)) {
+            level.move_handle(drag.handle, point + drag.offset);
+        }
+        if actions.just_pressed(Action::Delete) {
+            if let Some(handle) = level.handle_at(editor.tool, point) {
+                let before = level.clone();
+                if level.delete(handle) {
+                    editor.history.record(before);
+                    committed = true;
+                }
+            }
+        }
+    }
+    if editor.drag.is_some() && !actions.pressed(Action::Select) {
+        editor.drag = None;
+        level.finish_edit();
+        editor.history.discard_if_unchanged(level);
+        committed = true;
+    }
+
+    if actions.just_pressed(Action::Undo) {
+        committed |= editor.history.undo(level);
+    }
+    if actions.just_pressed(Action::Redo) {
+        committed |= editor.history.redo(level);
+    }
+
+    if committed {
+        if let Err(err) = level.validate() {
+            warn!("{err}, not applying it until it is fixed");
+        } else if let Some(asset) = levels.get_mut(&current.handle) {
+            if asset != level {
+                *asset = level.clone();
+            }
+        }
+    }
+    if actions.just_pressed(Action::Save) {
+        let path = format!("assets/{}", current.path);
+        match level.save(&path) {
+            Ok(()) => info!("Saved level to {path}"),
+            Err(err) => error!("{err}"),
+        }
+    }
+}
+
+fn draw_cross(lines: &mut DebugLines, center: Vec3, size: f32, color: Color) {
+    lines.line_colored(center - Vec3::X * size, center + Vec3::X * size, 0.0, color);
+    lines.line_colored(center - Vec3::Z * size, center + Vec3::Z * size, 0.0, color);
+    lines.line_colored(center - Vec3::Y * size, center + Vec3::Y * size, 0.0, color);
+}
+
+// Draws the working copy of the level, which may be mid-drag and not yet applied.
+fn draw_editor(editor: Res<Editor>, terrain: Res<Terrain>, mut lines: ResMut<DebugLines>) {
+    let Some(level) = &editor.level else {
+        return;
+    };
+    let route = level.route_on(&terrain);
+    let active = |tool: EditorTool| {
+        if editor.tool == tool {
+            Color::WHITE
+        } else {
+            Color::GRAY
+        }
+    };
+
+    for (layer, tool) in [
+        (PathLayer::Ground, EditorTool::GroundPath),
+        (PathLayer::Air, EditorTool::AirPath),
+    ] {
+        let mut from = route.spawn;
+        for waypoint in route.path(layer).iter().chain([&route.goal]) {
+            lines.line_colored(from, *waypoint, 0.0, active(tool));
+            from = *waypoint;
+        }
+        for waypoint in route.path(layer) {
+            draw_cross(&mut lines, *waypoint, HANDLE_SIZE, active(tool));
+        }
+    }
+    draw_cross(&mut lines, route.spawn, HANDLE_SIZE * 2.0, Color::GREEN);
+    draw_cross(&mut lines, route.goal, HANDLE_SIZE * 2.0, Color::RED);
+
+    for area in &level.buildable {
+        let corners = [
+            Vec3::new(area.min.x, 0.01, area.min.y),
+            Vec3::new(area.max.x, 0.01, area.min.y),
+            Vec3::new(area.max.x, 0.01, area.max.y),
+            Vec3::new(area.min.x, 0.01, area.max.y),
+        ]
+        .map(|corner| terrain.snap(corner));
+        for i in 0..corners.len() {
+            let next = corners[(i + 1) % corners.len()];
+            lines.line_colored(corners[i], next, 0.0, active(EditorTool::Buildable));
+        }
+        draw_cross(
+            &mut lines,
+            corners[2],
+            HANDLE_SIZE,
+            active(EditorTool::Buildable),
+        );
+    }
+
+    for prop in &level.props {
+        draw_cross(
+            &mut lines,
+            terrain.snap(prop.position),
+            HANDLE_SIZE,
+            active(EditorTool::Props),
+        );
+    }
+}
+
+fn spawn_editor_text(mut commands: Commands, assets: Res<GameAssets>) {
+    commands
+        .spawn(
+            TextBundle::from_section("", text_style(&assets, 18.0)).with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(Visibility::Hidden)
+        .insert(EditorText)
+        .insert(Name::new("EditorText"));
+}
+
+fn update_editor_text(editor: Res<Editor>, mut text: Query<&mut Text, With<EditorText>>) {
+    if !editor.is_changed() {
+        return;
+    }
+    for mut text in &mut text {
+        text.sections[0].value = format!(
+            "Editing: {:?} tool\nTab: next tool, click: place or drag, X: delete, Z: undo, \
+             Y: redo, F5: save, F2: play",
+            editor.tool
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_field::*;
+
+    fn level() -> LevelAsset {
+        let bytes = std::fs::read(format!("assets/{DEFAULT_LEVEL}")).unwrap();
+        LevelAsset::parse(&bytes).unwrap()
+    }
+
+    #[test]
+    fn waypoints_are_inserted_where_they_detour_least() {
+        let mut level = level();
+        level.route.spawn = Vec3::new(-2.0, 0.5, 0.0);
+        level.route.goal = Vec3::new(2.0, 0.5, 0.0);
+        level.route.air_path = vec![Vec3::new(0.0, 0.5, 0.0)];
+
+        assert_eq!(
+            level.insert_waypoint(PathLayer::Air, Vec3::new(1.0, 0.0, 0.1)),
+            1
+        );
+        assert_eq!(
+            level.insert_waypoint(PathLayer::Air, Vec3::new(-1.0, 0.0, 0.1)),
+            0
+        );
+        // Waypoints stay at the height of the path.
+        assert_eq!(level.route.air_path[0], Vec3::new(-1.0, 0.5, 0.1));
+    }
+
+    #[test]
+    fn moved_ground_waypoints_change_where_ground_targets_walk() {
+        let mut level = level();
+        let grid = level.nav_grid();
+        let terrain = Terrain::default();
+        let walk = |level: &LevelAsset| {
+            let route = level.route_on(&terrain);
+            RouteFlowFields::new(&grid, &route)
+                .walk(&grid, route.spawn)
+                .unwrap()
+        };
+        let old = level.route.ground_path[0];
+        let new = Vec3::new(old.x, old.y, -0.5);
+        assert!(walk(&level).contains(&grid.nearest_cell(old)));
+
+        level.move_handle(EditHandle::Waypoint(PathLayer::Ground, 0), new);
+        let cells = walk(&level);
+        assert!(cells.contains(&grid.nearest_cell(new)));
+        assert!(!cells.contains(&grid.nearest_cell(old)));
+    }
+
+    #[test]
+    fn dragged_out_areas_are_normalized_and_empty_ones_dropped() {
+        let mut level = level();
+        level.buildable.clear();
+        let corner = level.place(EditorTool::Buildable, Vec3::new(1.0, 0.0, 1.0));
+        level.move_handle(corner, Vec3::new(-1.0, 0.0, 0.0));
+        level.place(EditorTool::Buildable, Vec3::ZERO);
+        level.finish_edit();
+        assert_eq!(
+            level.buildable,
+            vec![BuildableArea {
+                min: Vec2::new(-1.0, 0.0),
+                max: Vec2::new(1.0, 1.0),
+            }]
+        );
+        assert_eq!(
+            level.handle_at(EditorTool::Buildable, Vec3::new(0.0, 0.0, 0.5)),
+            Some(EditHandle::Area(0))
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_restore_snapshots() {
+        let mut level = level();
+        let original = level.clone();
+        let mut history = EditHistory::default();
+
+        history.record(level.clone());
+        level.place(EditorTool::Props, Vec3::ONE);
+        let edited = level.clone();
+
+        assert!(history.undo(&mut level));
+        assert_eq!(level, original);
+        assert!(!history.undo(&mut level));
+        assert!(history.redo(&mut level));
+        assert_eq!(level, edited);
+
+        // A new edit after undoing can't be redone past.
+        assert!(history.undo(&mut level));
+        history.record(level.clone());
+        assert!(!history.redo(&mut level));
+    }
+
+    #[test]
+    fn unchanged_edits_leave_no_history() {
+        let mut level = level();
+        let mut history = EditHistory::default();
+        history.record(level.clone());
+        history.discard_if_unchanged(&level);
+        assert!(!history.undo(&mut level));
+    }
+}
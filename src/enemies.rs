@@ -0,0 +1,570 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::components::*;
+use crate::gap_timer::*;
+use crate::health::*;
+use crate::highlight::*;
+use crate::steering::*;
+use crate::target::*;
+
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Collider;
+use serde::{Deserialize, Serialize};
+
+// Relative to the assets folder.
+pub const ENEMIES_PATH: &str = "enemies.ron";
+// The catalog shipped with the game, for when the one in the assets folder can't be loaded.
+const BUILT_IN_ENEMIES: &str = include_str!("../assets/enemies.ron");
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Reflect,
+    FromReflect,
+    Serialize,
+    Deserialize,
+)]
+pub enum EnemyKind {
+    // Fast and fragile.
+    #[default]
+    Runner,
+    // Slow, armored and shielded.
+    Tank,
+    // Flies over the map, ignoring ground paths.
+    Flyer,
+    // Spawns smaller enemies when it dies.
+    Splitter,
+    // Heals nearby allies.
+    Healer,
+    // Very tough, and changes behavior as it loses health.
+    Boss,
+}
+
+impl EnemyKind {
+    pub const ALL: [EnemyKind; 6] = [
+        EnemyKind::Runner,
+        EnemyKind::Tank,
+        EnemyKind::Flyer,
+        EnemyKind::Splitter,
+        EnemyKind::Healer,
+        EnemyKind::Boss,
+    ];
+}
+
+// Collider shapes, before the enemy's scale is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ColliderShape {
+    Ball { radius: f32 },
+    Capsule { half_height: f32, radius: f32 },
+    Cuboid { half_extents: (f32, f32, f32) },
+}
+
+impl ColliderShape {
+    pub fn collider(&self) -> Collider {
+        match *self {
+            ColliderShape::Ball { radius } => Collider::ball(radius),
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => Collider::capsule_y(half_height, radius),
+            ColliderShape::Cuboid {
+                half_extents: (x, y, z),
+            } => Collider::cuboid(x, y, z),
+        }
+    }
+
+    // Radius of a sphere containing the shape.
+    pub fn radius(&self) -> f32 {
+        match *self {
+            ColliderShape::Ball { radius } => radius,
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => half_height + radius,
+            ColliderShape::Cuboid {
+                half_extents: (x, y, z),
+            } => Vec3::new(x, y, z).length(),
+        }
+    }
+}
+
+// A number of enemies of one kind, spawned together.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct EnemyGroup {
+    pub kind: EnemyKind,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HealAuraDefinition {
+    pub radius: f32,
+    // Health restored to each ally in range per pulse.
+    pub amount: f32,
+    // Seconds between pulses.
+    pub period: f32,
+}
+
+// Changes applied once when an enemy's health drops to `threshold`, a fraction of max health.
+#[derive(Debug, Clone, PartialEq, Default, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct BossPhase {
+    pub threshold: f32,
+    #[serde(default = "default_scale")]
+    pub speed_multiplier: f32,
+    #[serde(default)]
+    pub minions: Option<EnemyGroup>,
+    // Replaces any remaining shield.
+    #[serde(default)]
+    pub shield: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnemyDefinition {
+    // Scene path, relative to the assets directory.
+    pub model: String,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    pub health: f32,
+    pub speed: f32,
+    pub bounty: u32,
+    #[serde(default)]
+    pub shield: f32,
+    #[serde(default)]
+    pub armor: f32,
+    #[serde(default)]
+    pub regeneration: f32,
+    pub collider: ColliderShape,
+    // Flying enemies move at this height above the ground.
+    #[serde(default)]
+    pub altitude: Option<f32>,
+    #[serde(default)]
+    pub split: Option<EnemyGroup>,
+    #[serde(default)]
+    pub heal_aura: Option<HealAuraDefinition>,
+    // Enemies with phases are bosses. Ordered by decreasing threshold.
+    #[serde(default)]
+    pub phases: Vec<BossPhase>,
+    #[serde(skip)]
+    pub scene: Handle<Scene>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Missing(EnemyKind),
+    // Following `split` from this kind never ends.
+    SplitCycle(EnemyKind),
+    Invalid(EnemyKind, &'static str),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(err) => write!(f, "failed to read enemy catalog: {err}"),
+            CatalogError::Parse(err) => write!(f, "failed to parse enemy catalog: {err}"),
+            CatalogError::Missing(kind) => write!(f, "no definition for {kind:?}"),
+            CatalogError::SplitCycle(kind) => write!(f, "{kind:?} splits forever"),
+            CatalogError::Invalid(kind, reason) => write!(f, "invalid {kind:?}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<std::io::Error> for CatalogError {
+    fn from(err: std::io::Error) -> Self {
+        CatalogError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for CatalogError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        CatalogError::Parse(err)
+    }
+}
+
+// Definitions of every kind of enemy. Validated on load, so every kind is present.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+pub struct EnemyCatalog {
+    enemies: BTreeMap<EnemyKind, EnemyDefinition>,
+}
+
+impl Default for EnemyCatalog {
+    fn default() -> Self {
+        ron::from_str(BUILT_IN_ENEMIES).expect("built-in enemy catalog is invalid")
+    }
+}
+
+impl EnemyCatalog {
+    pub fn get(&self, kind: EnemyKind) -> &EnemyDefinition {
+        &self.enemies[&kind]
+    }
+
+    pub fn validate(&self) -> Result<(), CatalogError> {
+        for kind in EnemyKind::ALL {
+            let Some(definition) = self.enemies.get(&kind) else {
+                return Err(CatalogError::Missing(kind));
+            };
+            if definition.health <= 0.0 {
+                return Err(CatalogError::Invalid(kind, "health must be positive"));
+            }
+            if definition.scale <= 0.0 {
+                return Err(CatalogError::Invalid(kind, "scale must be positive"));
+            }
+            let mut previous_threshold = 1.0;
+            for phase in &definition.phases {
+                if phase.threshold <= 0.0 || phase.threshold > previous_threshold {
+                    return Err(CatalogError::Invalid(
+                        kind,
+                        "phase thresholds must decrease within (0, 1]",
+                    ));
+                }
+                previous_threshold = phase.threshold;
+            }
+
+            // A chain of splits longer than the number of kinds must revisit one.
+            let mut next = definition.split;
+            for _ in 0..EnemyKind::ALL.len() {
+                next = next.and_then(|split| self.enemies.get(&split.kind)?.split);
+            }
+            if next.is_some() {
+                return Err(CatalogError::SplitCycle(kind));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CatalogError> {
+        let contents = std::fs::read_to_string(path)?;
+        let catalog: EnemyCatalog = ron::from_str(&contents)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    // A missing or broken catalog file is logged, rather than stopping the game.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        EnemyCatalog::load(path).unwrap_or_else(|err| {
+            error!("Using the built-in enemy catalog: {err}");
+            EnemyCatalog::default()
+        })
+    }
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Enemy {
+    pub kind: EnemyKind,
+}
+
+// Spawns enemies of `kind` when the entity dies.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Splits {
+    pub kind: EnemyKind,
+    pub count: u32,
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Boss {
+    pub phases: Vec<BossPhase>,
+    // Number of phases which have started.
+    pub reached: usize,
+}
+
+// Periodically heals live targets within `radius`, other than itself.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct HealAura {
+    pub radius: f32,
+    pub amount: f32,
+    pub timer: GapTimer,
+}
+
+pub struct EnemiesPlugin {}
+
+impl Plugin for EnemiesPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<EnemyKind>()
+            .register_type::<Enemy>()
+            .register_type::<Splits>()
+            .register_type::<HealAura>()
+            .register_type::<Boss>()
+            .add_startup_system(load_enemy_catalog.in_base_set(StartupSet::PreStartup))
+            .add_system(split_on_death)
+            .add_system(advance_boss_phases.before(TargetSet::Navigate))
+            .add_system(pulse_heal_auras);
+    }
+}
+
+fn load_enemy_catalog(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // Found the same way the asset server finds assets, not relative to the working directory.
+    let path = FileAssetIo::get_base_path()
+        .join("assets")
+        .join(ENEMIES_PATH);
+    let mut catalog = EnemyCatalog::load_or_default(path);
+    for definition in catalog.enemies.values_mut() {
+        definition.scene = asset_server.load(definition.model.as_str());
+    }
+    commands.insert_resource(catalog);
+}
+
+// Spawns an enemy on the ground at `location`, or at its altitude if it flies, heading in
+// `direction`. Health is scaled by `health_multiplier` so waves can toughen enemies.
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    catalog: &EnemyCatalog,
+    kind: EnemyKind,
+    location: Vec3,
+    direction: Vec3,
+    health_multiplier: f32,
+) -> Entity {
+    let definition = catalog.get(kind);
+    let mut transform =
+        Transform::from_translation(location).with_scale(Vec3::splat(definition.scale));
+    if let Some(altitude) = definition.altitude {
+        transform.translation.y += altitude;
+    }
+    let target = TargetBundle::new(
+        definition.health * health_multiplier,
+        direction.normalize_or_zero() * definition.speed,
+        definition.collider.radius() * definition.scale,
+    );
+    let entity = spawn_target(
+        commands,
+        definition.scene.clone(),
+        transform,
+        target,
+        definition.collider.collider(),
+    );
+
+    let mut entity_cmd = commands.entity(entity);
+    entity_cmd
+        .insert(Enemy { kind })
+        .insert(Steering::new(
+            direction.normalize_or_zero() * definition.speed,
+        ))
+        .insert(Bounty {
+            gold: definition.bounty,
+        })
+        .insert(Name::new(format!("{kind:?}")));
+    if definition.shield > 0.0 {
+        entity_cmd.insert(Shield::new(definition.shield));
+    }
+    if definition.armor > 0.0 {
+        entity_cmd.insert(Armor {
+            val: definition.armor,
+        });
+    }
+    if definition.regeneration > 0.0 {
+        entity_cmd.insert(Regeneration {
+            per_second: definition.regeneration,
+        });
+    }
+    if let Some(altitude) = definition.altitude {
+        entity_cmd.insert(Flying { altitude });
+    }
+    if let Some(split) = definition.split {
+        entity_cmd.insert(Splits {
+            kind: split.kind,
+            count: split.count,
+        });
+    }
+    if let Some(aura) = definition.heal_aura {
+        entity_cmd.insert(HealAura {
+            radius: aura.radius,
+            amount: aura.amount,
+            timer: GapTimer::from_seconds(aura.period),
+        });
+    }
+    if !definition.phases.is_empty() {
+        entity_cmd.insert(Boss {
+            phases: definition.phases.clone(),
+            reached: 0,
+        });
+    }
+    entity
+}
+
+// Spawns `group` around an existing enemy, continuing along the path from where it is.
+fn spawn_group_around(
+    commands: &mut Commands,
+    catalog: &EnemyCatalog,
+    group: EnemyGroup,
+    transform: &GlobalTransform,
+    velocity: &Velocity,
+    follower: &PathFollower,
+    flying: Option<&Flying>,
+) {
+    let direction = velocity.val.normalize_or_zero();
+    // Spread them out in a line across the direction of travel.
+    let across = direction.cross(Vec3::Y).normalize_or_zero();
+    // Spawned enemies add their own altitude, so start from the ground.
+    let mut location = transform.translation();
+    location.y -= flying.map_or(0.0, |flying| flying.altitude);
+    for i in 0..group.count {
+        let offset = (i as f32 - (group.count - 1) as f32 / 2.0) * 0.3;
+        let entity = spawn_enemy(
+            commands,
+            catalog,
+            group.kind,
+            location + across * offset,
+            direction,
+            1.0,
+        );
+        // Progress only carries over between enemies on the same path.
+        if catalog.get(group.kind).altitude.is_some() == flying.is_some() {
+            commands.entity(entity).insert(follower.clone());
+        }
+    }
+}
+
+fn split_on_death(
+    mut commands: Commands,
+    mut died: EventReader<Died>,
+    splitters: Query<(
+        &Splits,
+        &GlobalTransform,
+        &Velocity,
+        &PathFollower,
+        Option<&Flying>,
+    )>,
+    catalog: Res<EnemyCatalog>,
+) {
+    for event in died.iter() {
+        let Ok((splits, transform, velocity, follower, flying)) = splitters.get(event.entity)
+        else {
+            continue;
+        };
+        let group = EnemyGroup {
+            kind: splits.kind,
+            count: splits.count,
+        };
+        spawn_group_around(
+            &mut commands,
+            &catalog,
+            group,
+            transform,
+            velocity,
+            follower,
+            flying,
+        );
+    }
+}
+
+// Starts every phase whose threshold the boss's health has dropped to. A big hit can start
+// several phases at once.
+fn advance_boss_phases(
+    mut commands: Commands,
+    mut bosses: Query<
+        (
+            Entity,
+            &mut Boss,
+            &Health,
+            &mut Velocity,
+            &GlobalTransform,
+            &PathFollower,
+            Option<&Flying>,
+        ),
+        (Changed<Health>, Without<Dead>),
+    >,
+    catalog: Res<EnemyCatalog>,
+) {
+    for (entity, mut boss, health, mut velocity, transform, follower, flying) in &mut bosses {
+        while let Some(phase) = boss.phases.get(boss.reached).cloned() {
+            if health.fraction() > phase.threshold {
+                break;
+            }
+            boss.reached += 1;
+            velocity.val *= phase.speed_multiplier;
+            if let Some(minions) = phase.minions {
+                spawn_group_around(
+                    &mut commands,
+                    &catalog,
+                    minions,
+                    transform,
+                    &velocity,
+                    follower,
+                    flying,
+                );
+            }
+            if let Some(shield) = phase.shield {
+                insert_if_exists(&mut commands, entity, Shield::new(shield));
+            }
+        }
+    }
+}
+
+fn pulse_heal_auras(
+    mut healers: Query<(Entity, &GlobalTransform, &mut HealAura), Without<Dead>>,
+    targets: Query<(Entity, &GlobalTransform), (With<Target>, Without<Dead>)>,
+    mut heals: EventWriter<HealEvent>,
+    time: Res<Time>,
+) {
+    for (healer, healer_transform, mut aura) in &mut healers {
+        if aura.timer.tick_game(&time) == 0 {
+            continue;
+        }
+        for (target, target_transform) in &targets {
+            let distance = healer_transform
+                .translation()
+                .distance(target_transform.translation());
+            if target != healer && distance <= aura.radius {
+                heals.send(HealEvent {
+                    target,
+                    amount: aura.amount,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_file_is_valid() {
+        EnemyCatalog::load(Path::new("assets").join(ENEMIES_PATH)).unwrap();
+        EnemyCatalog::default().validate().unwrap();
+    }
+
+    #[test]
+    fn missing_catalog_falls_back_to_built_in() {
+        let catalog = EnemyCatalog::load_or_default("assets/missing.ron");
+        assert_eq!(catalog.get(EnemyKind::Runner).health, 6.0);
+    }
+
+    #[test]
+    fn split_cycle_is_rejected() {
+        let mut catalog = EnemyCatalog::default();
+        for (kind, definition) in catalog.enemies.iter_mut() {
+            definition.split = Some(EnemyGroup {
+                kind: if *kind == EnemyKind::Runner {
+                    EnemyKind::Tank
+                } else {
+                    EnemyKind::Runner
+                },
+                count: 1,
+            });
+        }
+        assert!(matches!(
+            catalog.validate(),
+            Err(CatalogError::SplitCycle(_))
+        ));
+    }
+}
@@ -0,0 +1,494 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::nav::*;
+use crate::resources::*;
+
+use bevy::prelude::*;
+
+const UNREACHABLE: u32 = u32::MAX;
+
+// Cost of the cheapest walk from every cell of the `NavGrid` to the goal, along with the next
+// cell on that walk. Lets any number of ground targets find their way by looking up the cell
+// they stand in, instead of each searching for its own path.
+#[derive(Debug, Clone)]
+pub struct FlowField {
+    goal: Vec3,
+    goal_cell: IVec2,
+    size: IVec2,
+    cost: Vec<u32>,
+    next: Vec<Option<IVec2>>,
+    // Blocked cells of the grid as of the last update, to find which cells changed since.
+    blocked: Vec<bool>,
+}
+
+impl FlowField {
+    pub fn new(grid: &NavGrid, goal: Vec3) -> Self {
+        let size = grid.size();
+        let cells = (size.x * size.y) as usize;
+        let mut field = Self {
+            goal,
+            goal_cell: grid.nearest_cell(goal),
+            size,
+            cost: vec![UNREACHABLE; cells],
+            next: vec![None; cells],
+            blocked: vec![false; cells],
+        };
+        field.snapshot(grid);
+
+        let mut open = BinaryHeap::new();
+        if !grid.is_blocked(field.goal_cell) {
+            let index = field.index(field.goal_cell);
+            field.cost[index] = 0;
+            open.push(Reverse((0, field.goal_cell.x, field.goal_cell.y)));
+        }
+        field.propagate(grid, open);
+        field
+    }
+
+    pub fn goal(&self) -> Vec3 {
+        self.goal
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size).all()
+    }
+
+    fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let size = self.size;
+        (0..size.y).flat_map(move |z| (0..size.x).map(move |x| IVec2::new(x, z)))
+    }
+
+    fn snapshot(&mut self, grid: &NavGrid) {
+        for cell in self.cells() {
+            let index = self.index(cell);
+            self.blocked[index] = grid.is_blocked(cell);
+        }
+    }
+
+    // Cost of walking from `cell` to the goal, in the grid's units of 10 per cell.
+    pub fn cost(&self, cell: IVec2) -> Option<u32> {
+        if !self.in_bounds(cell) {
+            return None;
+        }
+        let cost = self.cost[self.index(cell)];
+        (cost != UNREACHABLE).then_some(cost)
+    }
+
+    // The cell to walk to from `cell`, if the goal can be reached from it.
+    pub fn next(&self, cell: IVec2) -> Option<IVec2> {
+        if !self.in_bounds(cell) {
+            return None;
+        }
+        self.next[self.index(cell)]
+    }
+
+    // Dijkstra outwards from the cells in `open`, lowering the cost of any cell which can reach
+    // the goal more cheaply through them.
+    fn propagate(&mut self, grid: &NavGrid, mut open: BinaryHeap<Reverse<(u32, i32, i32)>>) {
+        while let Some(Reverse((cell_cost, x, y))) = open.pop() {
+            let cell = IVec2::new(x, y);
+            if cell_cost > self.cost[self.index(cell)] {
+                continue;
+            }
+            // Moves are symmetric, so the neighbors which `cell` can walk to can walk to it.
+            for (neighbor, step) in grid.neighbors(cell) {
+                let neighbor_cost = cell_cost + step;
+                let index = self.index(neighbor);
+                if neighbor_cost < self.cost[index] {
+                    self.cost[index] = neighbor_cost;
+                    self.next[index] = Some(cell);
+                    open.push(Reverse((neighbor_cost, neighbor.x, neighbor.y)));
+                }
+            }
+        }
+    }
+
+    // Brings the field up to date with the grid, only recomputing the cells which are affected
+    // by cells that were blocked or cleared since the last update.
+    pub fn update(&mut self, grid: &NavGrid) {
+        if grid.size() != self.size || grid.nearest_cell(self.goal) != self.goal_cell {
+            *self = Self::new(grid, self.goal);
+            return;
+        }
+        let changed: Vec<IVec2> = self
+            .cells()
+            .filter(|cell| self.blocked[self.index(*cell)] != grid.is_blocked(*cell))
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        self.snapshot(grid);
+
+        // Newly blocked cells cut every walk through them, as well as diagonal moves past their
+        // corners.
+        let mut cut = Vec::new();
+        for cell in changed.iter().filter(|cell| grid.is_blocked(**cell)) {
+            cut.push(*cell);
+            for corner in grid_neighbors(*cell) {
+                let Some(next) = self.next(corner) else {
+                    continue;
+                };
+                let step = next - corner;
+                let diagonal = step.x != 0 && step.y != 0;
+                let passes = corner + IVec2::new(step.x, 0) == *cell
+                    || corner + IVec2::new(0, step.y) == *cell;
+                if diagonal && passes {
+                    cut.push(corner);
+                }
+            }
+        }
+        let invalid = self.invalidate(&cut);
+
+        // Restart from the cells bordering the invalidated ones, and around cleared cells, which
+        // may offer shorter walks to everything nearby.
+        let mut open = BinaryHeap::new();
+        let mut seed = |field: &Self, cell: IVec2| {
+            if let Some(cost) = field.cost(cell) {
+                open.push(Reverse((cost, cell.x, cell.y)));
+            }
+        };
+        for cell in &invalid {
+            for neighbor in grid_neighbors(*cell) {
+                seed(self, neighbor);
+            }
+        }
+        for cell in changed.iter().filter(|cell| !grid.is_blocked(**cell)) {
+            if *cell == self.goal_cell {
+                let index = self.index(*cell);
+                self.cost[index] = 0;
+                self.next[index] = None;
+            }
+            seed(self, *cell);
+            for neighbor in grid_neighbors(*cell) {
+                seed(self, neighbor);
+            }
+        }
+        self.propagate(grid, open);
+    }
+
+    // Marks `roots` and every cell whose walk passes through them as unreachable, returning them.
+    fn invalidate(&mut self, roots: &[IVec2]) -> Vec<IVec2> {
+        if roots.is_empty() {
+            return Vec::new();
+        }
+        let mut children: Vec<Vec<IVec2>> = vec![Vec::new(); self.cost.len()];
+        for cell in self.cells() {
+            if let Some(next) = self.next(cell) {
+                children[self.index(next)].push(cell);
+            }
+        }
+
+        let mut invalid = Vec::new();
+        let mut stack = roots.to_vec();
+        while let Some(cell) = stack.pop() {
+            let index = self.index(cell);
+            // Already invalidated, or was never reachable.
+            if self.cost[index] == UNREACHABLE {
+                continue;
+            }
+            self.cost[index] = UNREACHABLE;
+            self.next[index] = None;
+            invalid.push(cell);
+            stack.append(&mut children[index]);
+        }
+        invalid
+    }
+
+    // Direction along the ground to walk in from `position`. `None` once in the goal's cell, or
+    // if the goal can't be reached from here.
+    pub fn direction(&self, grid: &NavGrid, position: Vec3) -> Option<Vec3> {
+        let cell = grid.nearest_cell(position);
+        if cell == self.goal_cell {
+            return None;
+        }
+        // Targets standing where an obstacle was placed walk out to the cheapest neighbor.
+        let next = self.next(cell).or_else(|| {
+            grid_neighbors(cell)
+                .filter(|neighbor| !grid.is_blocked(*neighbor))
+                .filter_map(|neighbor| Some((self.cost(neighbor)?, neighbor)))
+                .min_by_key(|(cost, _)| *cost)
+                .map(|(_, neighbor)| neighbor)
+        })?;
+        let offset = grid.cell_center(next, position.y) - position;
+        offset.try_normalize()
+    }
+
+    // The cells a walker at `from` passes through on its way to the goal, both included, or
+    // `None` if it can't get there.
+    pub fn walk(&self, grid: &NavGrid, from: Vec3) -> Option<Vec<IVec2>> {
+        let mut cell = grid.nearest_cell(from);
+        let mut cells = vec![cell];
+        while cell != self.goal_cell {
+            cell = self.next(cell)?;
+            cells.push(cell);
+        }
+        Some(cells)
+    }
+
+    // Whether a target at `position` can still walk to the goal, possibly by first walking out of
+    // an obstacle placed on top of it.
+    pub fn reaches_goal(&self, grid: &NavGrid, position: Vec3) -> bool {
+        let cell = grid.nearest_cell(position);
+        cell == self.goal_cell
+            || self.cost(cell).is_some()
+            || grid_neighbors(cell)
+                .any(|neighbor| !grid.is_blocked(neighbor) && self.cost(neighbor).is_some())
+    }
+
+    // Walking distance from `position` to the goal.
+    pub fn distance(&self, grid: &NavGrid, position: Vec3) -> Option<f32> {
+        let cost = self.cost(grid.nearest_cell(position))?;
+        Some(cost as f32 / STRAIGHT_COST as f32 * grid.cell_size())
+    }
+}
+
+// A flow field for each leg of the route's ground path: to each of its waypoints in turn, then to
+// the goal. Ground targets find their way around towers between the waypoints, but still pass
+// through every one of them.
+#[derive(Debug, Clone, Resource)]
+pub struct RouteFlowFields {
+    // The ground path's waypoints followed by the goal, one for each field.
+    checkpoints: Vec<Vec3>,
+    fields: Vec<FlowField>,
+}
+
+impl RouteFlowFields {
+    pub fn new(grid: &NavGrid, route: &Route) -> Self {
+        let checkpoints = Self::checkpoints(route);
+        let fields = checkpoints
+            .iter()
+            .map(|checkpoint| FlowField::new(grid, *checkpoint))
+            .collect();
+        Self {
+            checkpoints,
+            fields,
+        }
+    }
+
+    fn checkpoints(route: &Route) -> Vec<Vec3> {
+        route
+            .ground_path
+            .iter()
+            .copied()
+            .chain(std::iter::once(route.goal))
+            .collect()
+    }
+
+    // Whether the fields lead through the route's current waypoints to its goal.
+    pub fn follows(&self, route: &Route) -> bool {
+        self.checkpoints == Self::checkpoints(route)
+    }
+
+    pub fn update(&mut self, grid: &NavGrid) {
+        for field in &mut self.fields {
+            field.update(grid);
+        }
+    }
+
+    // Index of the last leg, which leads to the goal. Targets past it keep following it.
+    fn last(&self) -> usize {
+        self.fields.len() - 1
+    }
+
+    // The field leading to the `index`th waypoint of the ground path, or to the goal.
+    pub fn field(&self, index: usize) -> &FlowField {
+        &self.fields[index.min(self.last())]
+    }
+
+    pub fn checkpoint(&self, index: usize) -> Vec3 {
+        self.checkpoints[index.min(self.last())]
+    }
+
+    // Whether a target at `position` heading for the `index`th waypoint can still walk through it
+    // and every later one to the goal.
+    pub fn reaches_goal(&self, grid: &NavGrid, position: Vec3, index: usize) -> bool {
+        let index = index.min(self.last());
+        self.fields[index].reaches_goal(grid, position)
+            && (index + 1..self.fields.len())
+                .all(|leg| self.fields[leg].reaches_goal(grid, self.checkpoints[leg - 1]))
+    }
+
+    // Walking distance from `position` through the `index`th waypoint and every later one to
+    // the goal.
+    pub fn distance(&self, grid: &NavGrid, position: Vec3, index: usize) -> Option<f32> {
+        let index = index.min(self.last());
+        let mut distance = self.fields[index].distance(grid, position)?;
+        for leg in index + 1..self.fields.len() {
+            distance += self.fields[leg].distance(grid, self.checkpoints[leg - 1])?;
+        }
+        Some(distance)
+    }
+
+    // The cells a walker at `from` passes through on its way through every waypoint to the goal,
+    // or `None` if it can't get there.
+    pub fn walk(&self, grid: &NavGrid, from: Vec3) -> Option<Vec<IVec2>> {
+        let mut cells = self.fields[0].walk(grid, from)?;
+        for leg in 1..self.fields.len() {
+            let walk = self.fields[leg].walk(grid, self.checkpoints[leg - 1])?;
+            // Each leg starts in the cell the one before ended in.
+            cells.extend(walk.into_iter().skip(1));
+        }
+        Some(cells)
+    }
+}
+
+fn grid_neighbors(cell: IVec2) -> impl Iterator<Item = IVec2> {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).map(move |z| IVec2::new(x, z)))
+        .filter(|offset| *offset != IVec2::ZERO)
+        .map(move |offset| cell + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic pseudo random cells for the incremental tests.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, below: i32) -> i32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((self.0 >> 33) % below as u64) as i32
+        }
+    }
+
+    fn grid() -> NavGrid {
+        NavGrid::new(Vec2::ZERO, Vec2::new(3.0, 2.0), 0.25)
+    }
+
+    fn assert_matches_full(field: &FlowField, grid: &NavGrid) {
+        let full = FlowField::new(grid, field.goal());
+        assert_eq!(field.cost, full.cost);
+        // Ties may be broken differently, but every step must still be a valid, cheapest move.
+        for cell in field.cells() {
+            let Some(next) = field.next(cell) else {
+                continue;
+            };
+            let step = grid
+                .neighbors(cell)
+                .find(|(neighbor, _)| *neighbor == next)
+                .map(|(_, step)| step);
+            assert!(step.is_some(), "{cell} can't walk to {next}");
+            assert_eq!(
+                field.cost(next).unwrap() + step.unwrap(),
+                field.cost(cell).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn costs_count_steps_to_goal() {
+        let grid = grid();
+        let field = FlowField::new(&grid, grid.cell_center(IVec2::ZERO, 0.0));
+        assert_eq!(field.cost(IVec2::ZERO), Some(0));
+        assert_eq!(field.cost(IVec2::new(3, 0)), Some(30));
+        assert_eq!(field.cost(IVec2::new(2, 2)), Some(28));
+        assert_eq!(field.next(IVec2::new(1, 1)), Some(IVec2::ZERO));
+    }
+
+    #[test]
+    fn walks_around_obstacles() {
+        let mut grid = grid();
+        let goal = grid.cell_center(IVec2::ZERO, 0.0);
+        for z in 0..7 {
+            grid.set_blocked(IVec2::new(1, z), true);
+        }
+        let field = FlowField::new(&grid, goal);
+        // Down past the end of the wall and back up.
+        assert_eq!(field.cost(IVec2::new(2, 0)), Some(160));
+        let direction = field
+            .direction(&grid, grid.cell_center(IVec2::new(2, 0), 0.0))
+            .unwrap();
+        assert_eq!(direction, Vec3::Z);
+    }
+
+    #[test]
+    fn sealed_off_cells_are_unreachable() {
+        let mut grid = grid();
+        let goal = grid.cell_center(IVec2::ZERO, 0.0);
+        for z in 0..grid.size().y {
+            grid.set_blocked(IVec2::new(1, z), true);
+        }
+        let field = FlowField::new(&grid, goal);
+        assert_eq!(field.cost(IVec2::new(2, 0)), None);
+        assert_eq!(field.direction(&grid, Vec3::new(1.0, 0.0, 1.0)), None);
+    }
+
+    #[test]
+    fn route_fields_pass_through_every_waypoint() {
+        let grid = grid();
+        let center = |x, z| grid.cell_center(IVec2::new(x, z), 0.0);
+        let route = Route {
+            spawn: center(0, 0),
+            goal: center(11, 0),
+            ground_path: vec![center(5, 7)],
+            ..default()
+        };
+        let fields = RouteFlowFields::new(&grid, &route);
+        let walk = fields.walk(&grid, route.spawn).unwrap();
+        assert_eq!(walk.first(), Some(&IVec2::new(0, 0)));
+        assert!(walk.contains(&IVec2::new(5, 7)));
+        assert_eq!(walk.last(), Some(&IVec2::new(11, 0)));
+        // Five diagonal steps and two straight ones down to the waypoint, then six and one back
+        // up, at a quarter per straight step.
+        let distance = fields.distance(&grid, route.spawn, 0).unwrap();
+        assert!((distance - 4.6).abs() < 1e-4, "{distance}");
+        // Past the waypoint, only the way to the goal is left.
+        assert_eq!(
+            fields.distance(&grid, center(5, 7), 1),
+            fields.field(1).distance(&grid, center(5, 7))
+        );
+        assert!(fields.follows(&route));
+        assert!(!fields.follows(&Route {
+            ground_path: Vec::new(),
+            ..route
+        }));
+    }
+
+    #[test]
+    fn sealed_off_waypoints_cant_be_reached() {
+        let empty = grid();
+        let center = |x, z| empty.cell_center(IVec2::new(x, z), 0.0);
+        let route = Route {
+            spawn: center(0, 0),
+            goal: center(2, 0),
+            ground_path: vec![center(10, 7)],
+            ..default()
+        };
+        // A wall between the spawn and the waypoint, which the goal is on the spawn's side of.
+        let mut grid = grid();
+        for z in 0..grid.size().y {
+            grid.set_blocked(IVec2::new(5, z), true);
+        }
+        let fields = RouteFlowFields::new(&grid, &route);
+        assert!(!fields.reaches_goal(&grid, route.spawn, 0));
+        assert!(fields.walk(&grid, route.spawn).is_none());
+        // Once past the waypoint, the goal is beyond the wall too.
+        assert!(!fields.reaches_goal(&grid, center(10, 7), 1));
+        assert!(fields.reaches_goal(&grid, center(1, 1), 1));
+    }
+
+    #[test]
+    fn incremental_updates_match_full_recompute() {
+        let mut rng = Lcg(7);
+        let mut grid = grid();
+        let goal = grid.cell_center(IVec2::new(5, 3), 0.0);
+        let mut field = FlowField::new(&grid, goal);
+        for _ in 0..200 {
+            // Toggle a few cells at a time, like towers being built and sold.
+            for _ in 0..=rng.next(3) {
+                let cell = IVec2::new(rng.next(grid.size().x), rng.next(grid.size().y));
+                let blocked = grid.is_blocked(cell);
+                grid.set_blocked(cell, !blocked);
+            }
+            field.update(&grid);
+            assert_matches_full(&field, &grid);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+// Timer used to check for a specific gap in time.
+// Should only be ticked by a single system.
+//
+// Can be driven either by absolute timestamps (`tick_at`, `tick_raw`, `tick_game`) or, like
+// Bevy's `Timer`, by deltas (`tick`). Don't mix the two on one timer.
+#[derive(Debug, Clone, Default, PartialEq, Reflect, FromReflect)]
+pub struct GapTimer {
+    // Time of the last full tick.
+    last: Duration,
+    gap: Duration,
+    // Most recent time the timer was ticked with.
+    now: Duration,
+    times_finished_this_tick: u32,
+    // Set by `expire`, so that the next tick fires however early it is.
+    expired: bool,
+}
+
+impl GapTimer {
+    pub const fn new(gap: Duration) -> GapTimer {
+        GapTimer {
+            last: Duration::ZERO,
+            gap,
+            now: Duration::ZERO,
+            times_finished_this_tick: 0,
+            expired: false,
+        }
+    }
+
+    pub fn from_seconds(gap: f32) -> GapTimer {
+        GapTimer::new(Duration::from_secs_f32(gap))
+    }
+
+    pub fn gap(&self) -> Duration {
+        self.gap
+    }
+
+    pub fn set_gap(&mut self, gap: Duration) {
+        self.gap = gap;
+    }
+
+    // Time of the last full tick.
+    pub fn last(&self) -> Duration {
+        self.last
+    }
+
+    // Returns the number of ticks which have passed since the last full tick. If at least 1 tick
+    // has passed, resets the timer. Any partial gap left over is dropped.
+    //
+    // Times earlier than the last full tick count as no time passing. A zero gap fires exactly
+    // once per call.
+    pub fn tick_at(&mut self, time: Duration) -> u32 {
+        self.now = time;
+        let diff = time.saturating_sub(self.last);
+        self.times_finished_this_tick = if self.gap.is_zero() {
+            1
+        } else if diff < self.gap {
+            0
+        } else {
+            (diff.as_nanos() / self.gap.as_nanos()).min(u32::MAX as u128) as u32
+        };
+        if self.expired {
+            self.times_finished_this_tick = self.times_finished_this_tick.max(1);
+            self.expired = false;
+        }
+        if self.times_finished_this_tick > 0 {
+            self.last = time;
+        }
+        self.times_finished_this_tick
+    }
+
+    // Ticks with real time, which keeps running while the game is paused.
+    pub fn tick_raw(&mut self, time: &Time) -> u32 {
+        self.tick_at(time.raw_elapsed())
+    }
+
+    // Ticks with game time, which stops while the game is paused.
+    pub fn tick_game(&mut self, time: &Time) -> u32 {
+        self.tick_at(time.elapsed())
+    }
+
+    // `Timer` compatible interface: advance by `delta` since the previous tick.
+    pub fn tick(&mut self, delta: Duration) -> &Self {
+        self.tick_at(self.now + delta);
+        self
+    }
+
+    pub fn just_finished(&self) -> bool {
+        self.times_finished_this_tick > 0
+    }
+
+    // The timer is repeating, so it is only finished on the tick where it fires.
+    pub fn finished(&self) -> bool {
+        self.just_finished()
+    }
+
+    pub fn times_finished_this_tick(&self) -> u32 {
+        self.times_finished_this_tick
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.gap
+    }
+
+    // Time since the last full tick. An expired timer counts the whole gap as elapsed.
+    pub fn elapsed(&self) -> Duration {
+        let elapsed = self.now.saturating_sub(self.last);
+        if self.expired {
+            elapsed.max(self.gap)
+        } else {
+            elapsed
+        }
+    }
+
+    // Time until the next full tick.
+    pub fn remaining(&self) -> Duration {
+        self.gap.saturating_sub(self.elapsed())
+    }
+
+    // Fraction of the gap which has elapsed, in [0, 1].
+    pub fn percent(&self) -> f32 {
+        if self.gap.is_zero() {
+            return 1.0;
+        }
+        (self.elapsed().as_secs_f32() / self.gap.as_secs_f32()).min(1.0)
+    }
+
+    pub fn percent_left(&self) -> f32 {
+        1.0 - self.percent()
+    }
+
+    // Restart the gap from the most recent tick.
+    pub fn reset(&mut self) {
+        self.reset_at(self.now);
+    }
+
+    pub fn reset_at(&mut self, time: Duration) {
+        self.last = time;
+        self.now = time;
+        self.times_finished_this_tick = 0;
+        self.expired = false;
+    }
+
+    // Make the next tick fire regardless of how little time passes.
+    pub fn expire(&mut self) {
+        self.expired = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn no_tick_before_gap() {
+        let mut timer = GapTimer::new(ms(100));
+        assert_eq!(timer.tick_at(ms(0)), 0);
+        assert_eq!(timer.tick_at(ms(99)), 0);
+        assert!(!timer.just_finished());
+        assert_eq!(timer.last(), ms(0));
+    }
+
+    #[test]
+    fn ticks_once_at_gap() {
+        let mut timer = GapTimer::new(ms(100));
+        assert_eq!(timer.tick_at(ms(100)), 1);
+        assert!(timer.just_finished());
+        assert_eq!(timer.last(), ms(100));
+        assert_eq!(timer.tick_at(ms(150)), 0);
+        assert_eq!(timer.tick_at(ms(200)), 1);
+    }
+
+    #[test]
+    fn catches_up_on_multiple_gaps() {
+        let mut timer = GapTimer::new(ms(100));
+        assert_eq!(timer.tick_at(ms(350)), 3);
+        assert_eq!(timer.times_finished_this_tick(), 3);
+        // The partial gap is dropped, so the next tick is a full gap after the catch up.
+        assert_eq!(timer.tick_at(ms(420)), 0);
+        assert_eq!(timer.tick_at(ms(450)), 1);
+    }
+
+    #[test]
+    fn earlier_time_does_not_panic() {
+        let mut timer = GapTimer::new(ms(100));
+        assert_eq!(timer.tick_at(ms(500)), 5);
+        assert_eq!(timer.tick_at(ms(100)), 0);
+        assert_eq!(timer.last(), ms(500));
+        assert_eq!(timer.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn zero_gap_fires_every_tick() {
+        let mut timer = GapTimer::new(Duration::ZERO);
+        assert_eq!(timer.tick_at(ms(0)), 1);
+        assert_eq!(timer.tick_at(ms(0)), 1);
+        assert_eq!(timer.tick_at(ms(10)), 1);
+        assert_eq!(timer.percent(), 1.0);
+        assert_eq!(timer.remaining(), Duration::ZERO);
+    }
+
+    #[test]
+    fn huge_gap_count_saturates() {
+        let mut timer = GapTimer::new(Duration::from_nanos(1));
+        assert_eq!(timer.tick_at(Duration::from_secs(u64::MAX)), u32::MAX);
+    }
+
+    #[test]
+    fn delta_interface_matches_timer() {
+        let mut timer = GapTimer::from_seconds(0.1);
+        assert!(!timer.tick(ms(60)).just_finished());
+        assert!(timer.tick(ms(60)).just_finished());
+        assert!(timer.finished());
+        assert_eq!(timer.tick(ms(250)).times_finished_this_tick(), 2);
+        assert!(!timer.tick(ms(10)).just_finished());
+        assert!((timer.duration().as_secs_f32() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn elapsed_remaining_and_percent() {
+        let mut timer = GapTimer::new(ms(200));
+        timer.tick_at(ms(50));
+        assert_eq!(timer.elapsed(), ms(50));
+        assert_eq!(timer.remaining(), ms(150));
+        assert!((timer.percent() - 0.25).abs() < 1e-6);
+        assert!((timer.percent_left() - 0.75).abs() < 1e-6);
+
+        timer.tick_at(ms(1000));
+        assert_eq!(timer.elapsed(), Duration::ZERO);
+        assert_eq!(timer.remaining(), ms(200));
+    }
+
+    #[test]
+    fn reset_restarts_gap() {
+        let mut timer = GapTimer::new(ms(100));
+        timer.tick_at(ms(90));
+        timer.reset();
+        assert_eq!(timer.last(), ms(90));
+        assert_eq!(timer.tick_at(ms(150)), 0);
+        assert_eq!(timer.tick_at(ms(190)), 1);
+
+        timer.reset_at(ms(1000));
+        assert_eq!(timer.tick_at(ms(1050)), 0);
+        assert_eq!(timer.tick_at(ms(1100)), 1);
+    }
+
+    #[test]
+    fn expire_fires_next_tick() {
+        let mut timer = GapTimer::new(ms(100));
+        timer.tick_at(ms(500));
+        timer.expire();
+        assert_eq!(timer.tick_at(ms(510)), 1);
+        assert_eq!(timer.tick_at(ms(520)), 0);
+    }
+
+    #[test]
+    fn expire_fires_before_the_first_gap() {
+        let mut timer = GapTimer::new(ms(1000));
+        timer.tick_at(ms(200));
+        timer.expire();
+        assert_eq!(timer.remaining(), Duration::ZERO);
+        assert_eq!(timer.tick_at(ms(250)), 1);
+        assert_eq!(timer.last(), ms(250));
+        assert_eq!(timer.tick_at(ms(300)), 0);
+    }
+
+    #[test]
+    fn set_gap_applies_to_next_tick() {
+        let mut timer = GapTimer::new(ms(100));
+        timer.set_gap(ms(300));
+        assert_eq!(timer.gap(), ms(300));
+        assert_eq!(timer.tick_at(ms(200)), 0);
+        assert_eq!(timer.tick_at(ms(300)), 1);
+    }
+
+    #[test]
+    fn raw_time_keeps_ticking_while_paused() {
+        let mut time = Time::default();
+        let start = time.startup();
+        time.update_with_instant(start);
+        time.pause();
+        time.update_with_instant(start + ms(250));
+
+        let mut raw = GapTimer::new(ms(100));
+        let mut game = GapTimer::new(ms(100));
+        assert_eq!(raw.tick_raw(&time), 2);
+        assert_eq!(game.tick_game(&time), 0);
+    }
+}
@@ -0,0 +1,273 @@
+use bevy::prelude::*;
+
+// Armor can never reduce a hit below this fraction of its damage.
+pub const MIN_ARMORED_DAMAGE: f32 = 0.2;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Health {
+    pub val: f32,
+    pub max: f32,
+    // Source of the most recent damage, credited with the kill.
+    pub last_damaged_by: Option<Entity>,
+}
+
+impl Health {
+    // Full health.
+    pub fn new(max: f32) -> Self {
+        Self {
+            val: max,
+            max,
+            last_damaged_by: None,
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.val <= 0.0
+    }
+
+    // Fraction of max health remaining, in [0, 1].
+    pub fn fraction(&self) -> f32 {
+        if self.max <= 0.0 {
+            return 0.0;
+        }
+        (self.val / self.max).clamp(0.0, 1.0)
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        self.val = (self.val + amount).min(self.max);
+    }
+}
+
+// Absorbs damage before it reaches `Health`. Not restored by healing.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Shield {
+    pub val: f32,
+    pub max: f32,
+}
+
+impl Shield {
+    pub fn new(max: f32) -> Self {
+        Self { val: max, max }
+    }
+
+    // Returns the damage which got through the shield.
+    pub fn absorb(&mut self, damage: f32) -> f32 {
+        let absorbed = damage.min(self.val);
+        self.val -= absorbed;
+        damage - absorbed
+    }
+}
+
+// Flat reduction of every hit which gets through the shield.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Armor {
+    pub val: f32,
+}
+
+impl Armor {
+    pub fn reduce(&self, damage: f32) -> f32 {
+        (damage - self.val).max(damage * MIN_ARMORED_DAMAGE)
+    }
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Regeneration {
+    pub per_second: f32,
+}
+
+// All damage should go through this event, so that shields apply and deaths are detected.
+#[derive(Debug, Clone, Copy)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    // Whoever dealt the damage, e.g. the tower which fired the bullet.
+    pub source: Option<Entity>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HealEvent {
+    pub target: Entity,
+    pub amount: f32,
+}
+
+// Sent once, on the frame an entity's health reaches 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Died {
+    pub entity: Entity,
+    // Source of the killing blow, if it had one.
+    pub killer: Option<Entity>,
+}
+
+// Marks entities which have died. Dead entities can't be damaged, healed or regenerate, and
+// should be ignored by gameplay systems until they are despawned.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Dead;
+
+pub struct HealthPlugin {}
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Health>()
+            .register_type::<Shield>()
+            .register_type::<Armor>()
+            .register_type::<Regeneration>()
+            .register_type::<Dead>()
+            .add_event::<DamageEvent>()
+            .add_event::<HealEvent>()
+            .add_event::<Died>()
+            // Run after gameplay systems so that damage dealt this frame is applied this frame.
+            .add_systems(
+                (regenerate, apply_heals, apply_damage, detect_deaths)
+                    .chain()
+                    .in_base_set(CoreSet::PostUpdate),
+            );
+    }
+}
+
+fn regenerate(mut healths: Query<(&mut Health, &Regeneration), Without<Dead>>, time: Res<Time>) {
+    for (mut health, regeneration) in &mut healths {
+        if health.val < health.max {
+            health.heal(regeneration.per_second * time.delta_seconds());
+        }
+    }
+}
+
+fn apply_heals(mut events: EventReader<HealEvent>, mut healths: Query<&mut Health, Without<Dead>>) {
+    for event in events.iter() {
+        if let Ok(mut health) = healths.get_mut(event.target) {
+            health.heal(event.amount);
+        }
+    }
+}
+
+fn apply_damage(
+    mut events: EventReader<DamageEvent>,
+    mut healths: Query<(&mut Health, Option<&mut Shield>, Option<&Armor>), Without<Dead>>,
+) {
+    for event in events.iter() {
+        let Ok((mut health, shield, armor)) = healths.get_mut(event.target) else {
+            continue;
+        };
+        let mut damage = match shield {
+            Some(mut shield) => shield.absorb(event.amount),
+            None => event.amount,
+        };
+        if let Some(armor) = armor {
+            damage = armor.reduce(damage);
+        }
+        if damage > 0.0 {
+            health.val -= damage;
+            health.last_damaged_by = event.source;
+        }
+    }
+}
+
+// The only place which decides that an entity has died.
+fn detect_deaths(
+    mut commands: Commands,
+    healths: Query<(Entity, &Health), (Changed<Health>, Without<Dead>)>,
+    mut died: EventWriter<Died>,
+) {
+    for (entity, health) in &healths {
+        if health.is_dead() {
+            commands.entity(entity).insert(Dead);
+            died.send(Died {
+                entity,
+                killer: health.last_damaged_by,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    use std::time::Duration;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::default();
+        app.frame = Duration::from_millis(500);
+        app.add_plugin(HealthPlugin {});
+        // The first update only initializes time.
+        app.update();
+        app
+    }
+
+    fn health(app: &App, entity: Entity) -> f32 {
+        app.world.get::<Health>(entity).unwrap().val
+    }
+
+    fn damage(app: &mut App, target: Entity, amount: f32) {
+        app.world.send_event(DamageEvent {
+            target,
+            amount,
+            source: None,
+        });
+    }
+
+    #[test]
+    fn shields_absorb_damage_before_health() {
+        let mut app = app();
+        let entity = app.world.spawn((Health::new(10.0), Shield::new(4.0))).id();
+        damage(&mut app, entity, 3.0);
+        app.update();
+        assert_eq!(health(&app, entity), 10.0);
+        damage(&mut app, entity, 3.0);
+        app.update();
+        assert_eq!(app.world.get::<Shield>(entity).unwrap().val, 0.0);
+        assert_eq!(health(&app, entity), 8.0);
+    }
+
+    #[test]
+    fn heals_are_capped_at_max() {
+        let mut app = app();
+        let entity = app.world.spawn(Health::new(10.0)).id();
+        damage(&mut app, entity, 6.0);
+        app.update();
+        app.world.send_event(HealEvent {
+            target: entity,
+            amount: 20.0,
+        });
+        app.update();
+        assert_eq!(health(&app, entity), 10.0);
+    }
+
+    #[test]
+    fn regeneration_heals_over_time() {
+        let mut app = app();
+        let entity = app
+            .world
+            .spawn((Health::new(10.0), Regeneration { per_second: 2.0 }))
+            .id();
+        damage(&mut app, entity, 5.0);
+        app.update();
+        // Damage is applied after regeneration in the same frame.
+        assert_eq!(health(&app, entity), 5.0);
+        app.run(2);
+        assert_eq!(health(&app, entity), 7.0);
+        app.run(10);
+        assert_eq!(health(&app, entity), 10.0);
+    }
+
+    #[test]
+    fn died_is_sent_once_and_dead_inserted() {
+        let mut app = app();
+        let entity = app.world.spawn(Health::new(5.0)).id();
+        let mut reader = app.world.resource::<Events<Died>>().get_reader();
+        let mut died = 0;
+        for _ in 0..3 {
+            damage(&mut app, entity, 10.0);
+            app.update();
+            died += reader.iter(app.world.resource::<Events<Died>>()).count();
+        }
+        assert_eq!(died, 1);
+        assert!(app.world.get::<Dead>(entity).is_some());
+    }
+}
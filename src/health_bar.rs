@@ -0,0 +1,262 @@
+use crate::health::*;
+use crate::highlight::*;
+use crate::target::*;
+
+use bevy::pbr::{NotShadowCaster, NotShadowReceiver};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+const BAR_WIDTH: f32 = 0.6;
+const BAR_HEIGHT: f32 = 0.08;
+// Height of the bar above the center of targets.
+const BAR_OFFSET: f32 = 0.6;
+// Max health which gets a bar `BAR_WIDTH` wide. Bars grow with the square root of max health, so
+// that bosses stand out without their bars covering the screen.
+const BAR_REFERENCE_HEALTH: f32 = 10.0;
+const MIN_BAR_SCALE: f32 = 0.6;
+const MAX_BAR_SCALE: f32 = 3.0;
+
+// Root of a health bar, spawned as a child of the entity whose health it shows.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct HealthBar;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct HealthBarFill;
+
+// Gives an entity with `Health` which isn't a `Target`, like a tower, a health bar too. Targets
+// always have one.
+#[derive(Debug, Component, Reflect)]
+#[reflect(Component)]
+pub struct ShowHealthBar {
+    // Height of the bar above the entity's origin.
+    pub offset: f32,
+}
+
+impl Default for ShowHealthBar {
+    fn default() -> Self {
+        Self { offset: BAR_OFFSET }
+    }
+}
+
+// Width of the bar relative to `BAR_WIDTH`, for an entity with `max` health.
+fn bar_scale(max: f32) -> f32 {
+    (max.max(0.0) / BAR_REFERENCE_HEALTH)
+        .sqrt()
+        .clamp(MIN_BAR_SCALE, MAX_BAR_SCALE)
+}
+
+#[derive(Debug, Resource)]
+pub struct HealthBarAssets {
+    pub mesh: Handle<Mesh>,
+    pub background: Handle<StandardMaterial>,
+    pub fill: Handle<StandardMaterial>,
+}
+
+pub struct HealthBarPlugin {}
+
+impl Plugin for HealthBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<HealthBar>()
+            .register_type::<HealthBarFill>()
+            .register_type::<ShowHealthBar>()
+            .add_startup_system(load_health_bar_assets.in_base_set(StartupSet::PreStartup))
+            .add_system(spawn_health_bars)
+            .add_system(update_health_bars)
+            .add_system(
+                billboard_health_bars
+                    .in_base_set(CoreSet::PostUpdate)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+fn load_health_bar_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let unlit = |color| StandardMaterial {
+        base_color: color,
+        unlit: true,
+        ..default()
+    };
+    commands.insert_resource(HealthBarAssets {
+        mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
+            BAR_WIDTH, BAR_HEIGHT,
+        )))),
+        background: materials.add(unlit(Color::rgb(0.3, 0.0, 0.0))),
+        fill: materials.add(unlit(Color::rgb(0.1, 0.9, 0.1))),
+    });
+}
+
+fn spawn_health_bars(
+    mut commands: Commands,
+    assets: Res<HealthBarAssets>,
+    entities: Query<
+        (Entity, Option<&ShowHealthBar>),
+        (Or<(Added<Target>, Added<ShowHealthBar>)>, With<Health>),
+    >,
+) {
+    for (entity, show) in &entities {
+        let offset = show.map_or(BAR_OFFSET, |show| show.offset);
+        let bar_bundle = |material: &Handle<StandardMaterial>, z| PbrBundle {
+            mesh: assets.mesh.clone(),
+            material: material.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, z),
+            ..default()
+        };
+        commands.entity(entity).with_children(|child_cmd| {
+            child_cmd
+                .spawn(SpatialBundle {
+                    transform: Transform::from_xyz(0.0, offset, 0.0),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(HealthBar)
+                .insert(Name::new("HealthBar"))
+                .with_children(|bar| {
+                    bar.spawn(bar_bundle(&assets.background, 0.0))
+                        .insert((NotShadowCaster, NotShadowReceiver));
+                    // Slightly in front so that it draws over the background.
+                    bar.spawn(bar_bundle(&assets.fill, 0.001))
+                        .insert((NotShadowCaster, NotShadowReceiver))
+                        .insert(HealthBarFill);
+                });
+        });
+    }
+}
+
+// Only show bars for damaged or hovered entities which are still alive. Bars are wider for
+// entities with more max health.
+fn update_health_bars(
+    mut bars: Query<(&Parent, &Children, &mut Visibility, &mut Transform), With<HealthBar>>,
+    mut fills: Query<&mut Transform, (With<HealthBarFill>, Without<HealthBar>)>,
+    healths: Query<(&Health, Option<&Hovered>, Option<&Dead>)>,
+) {
+    for (parent, children, mut visibility, mut bar_transform) in &mut bars {
+        let Ok((health, hovered, dead)) = healths.get(parent.get()) else {
+            continue;
+        };
+        let fraction = health.fraction();
+        *visibility = if dead.is_none() && (fraction < 1.0 || hovered.is_some()) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        let scale = bar_scale(health.max);
+        if bar_transform.scale.x != scale {
+            bar_transform.scale.x = scale;
+        }
+
+        for child in children {
+            if let Ok(mut transform) = fills.get_mut(*child) {
+                // Shrink towards the left edge.
+                transform.scale.x = fraction;
+                transform.translation.x = -(1.0 - fraction) * BAR_WIDTH / 2.0;
+            }
+        }
+    }
+}
+
+// Rotate bars to face the camera, regardless of how their parent is rotated.
+fn billboard_health_bars(
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    parents: Query<&GlobalTransform, Without<HealthBar>>,
+    mut bars: Query<(&Parent, &mut Transform), With<HealthBar>>,
+) {
+    let Ok(camera) = camera_query.get_single() else {
+        return;
+    };
+    let (_, camera_rotation, _) = camera.to_scale_rotation_translation();
+    for (parent, mut transform) in &mut bars {
+        let Ok(parent_transform) = parents.get(parent.get()) else {
+            continue;
+        };
+        let (_, parent_rotation, _) = parent_transform.to_scale_rotation_translation();
+        transform.rotation = parent_rotation.inverse() * camera_rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::default();
+        app.insert_resource(HealthBarAssets {
+            mesh: Handle::default(),
+            background: Handle::default(),
+            fill: Handle::default(),
+        })
+        .add_systems((spawn_health_bars, apply_system_buffers, update_health_bars).chain());
+        app
+    }
+
+    fn bar(app: &App, entity: Entity) -> Entity {
+        let children = app.world.get::<Children>(entity).unwrap();
+        *children
+            .iter()
+            .find(|child| app.world.get::<HealthBar>(**child).is_some())
+            .unwrap()
+    }
+
+    fn bars_of(app: &App, entity: Entity) -> usize {
+        let children = app.world.get::<Children>(entity);
+        children.map_or(0, |children| {
+            children
+                .iter()
+                .filter(|child| app.world.get::<HealthBar>(**child).is_some())
+                .count()
+        })
+    }
+
+    #[test]
+    fn only_opted_in_entities_get_bars() {
+        let mut app = app();
+        let tower = app
+            .world
+            .spawn((Health::new(50.0), ShowHealthBar::default()))
+            .id();
+        let wall = app.world.spawn(Health::new(50.0)).id();
+        app.update();
+        assert_eq!(bars_of(&app, tower), 1);
+        assert_eq!(bars_of(&app, wall), 0);
+    }
+
+    #[test]
+    fn bars_show_when_damaged_or_hovered() {
+        let mut app = app();
+        let target = app
+            .world
+            .spawn((Health::new(10.0), ShowHealthBar::default()))
+            .id();
+        app.update();
+        let bar = bar(&app, target);
+        let visible = |app: &App| app.world.get::<Visibility>(bar) == Some(&Visibility::Inherited);
+        assert!(!visible(&app));
+
+        app.world.entity_mut(target).insert(Hovered);
+        app.update();
+        assert!(visible(&app));
+
+        app.world.entity_mut(target).remove::<Hovered>();
+        app.world.get_mut::<Health>(target).unwrap().val = 5.0;
+        app.update();
+        assert!(visible(&app));
+
+        app.world.entity_mut(target).insert(Dead);
+        app.update();
+        assert!(!visible(&app));
+    }
+
+    #[test]
+    fn bars_grow_with_max_health() {
+        assert_eq!(bar_scale(10.0), 1.0);
+        assert_eq!(bar_scale(40.0), 2.0);
+        assert_eq!(bar_scale(1.0), MIN_BAR_SCALE);
+        assert_eq!(bar_scale(10_000.0), MAX_BAR_SCALE);
+    }
+}
@@ -0,0 +1,151 @@
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+// Marks the entity under the cursor.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Hovered;
+
+// Marks the entity chosen by the player.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Selected;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect, FromReflect)]
+pub enum HighlightKind {
+    #[default]
+    Hover,
+    Select,
+}
+
+// Ring mesh spawned as a child of a highlighted entity. Since it is a child, it is despawned
+// along with the entity, and the entity itself is never modified.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct HighlightRing {
+    pub kind: HighlightKind,
+}
+
+#[derive(Debug, Resource)]
+pub struct HighlightAssets {
+    pub hover_mesh: Handle<Mesh>,
+    pub select_mesh: Handle<Mesh>,
+    pub hover_material: Handle<StandardMaterial>,
+    pub select_material: Handle<StandardMaterial>,
+}
+
+// Inserting into an entity which was despawned earlier in the frame panics, so check at apply time.
+pub fn insert_if_exists<C: Component>(commands: &mut Commands, entity: Entity, component: C) {
+    commands.add(move |world: &mut World| {
+        if let Some(mut entity) = world.get_entity_mut(entity) {
+            entity.insert(component);
+        }
+    });
+}
+
+pub fn remove_if_exists<C: Component>(commands: &mut Commands, entity: Entity) {
+    commands.add(move |world: &mut World| {
+        if let Some(mut entity) = world.get_entity_mut(entity) {
+            entity.remove::<C>();
+        }
+    });
+}
+
+pub struct HighlightPlugin {}
+
+impl Plugin for HighlightPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Hovered>()
+            .register_type::<Selected>()
+            .register_type::<HighlightRing>()
+            .add_startup_system(load_highlight_assets.in_base_set(StartupSet::PreStartup))
+            .add_system(update_highlight_rings.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+fn load_highlight_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let ring = |radius| {
+        Mesh::from(shape::Torus {
+            radius,
+            ring_radius: 0.03,
+            subdivisions_segments: 32,
+            subdivisions_sides: 8,
+        })
+    };
+    let unlit = |color| StandardMaterial {
+        base_color: color,
+        unlit: true,
+        ..default()
+    };
+    // Different sizes so that hover and select are both visible on the same entity.
+    commands.insert_resource(HighlightAssets {
+        hover_mesh: meshes.add(ring(0.8)),
+        select_mesh: meshes.add(ring(0.7)),
+        hover_material: materials.add(unlit(Color::YELLOW)),
+        select_material: materials.add(unlit(Color::LIME_GREEN)),
+    });
+}
+
+// Reconciles rings with the `Hovered` and `Selected` markers every frame, so that rings never
+// outlive the marker they represent.
+fn update_highlight_rings(
+    mut commands: Commands,
+    assets: Res<HighlightAssets>,
+    rings: Query<(Entity, &HighlightRing, &Parent)>,
+    hovered: Query<Entity, With<Hovered>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    let mut existing = HashSet::new();
+    for (ring_entity, ring, parent) in &rings {
+        let still_marked = match ring.kind {
+            HighlightKind::Hover => hovered.contains(parent.get()),
+            HighlightKind::Select => selected.contains(parent.get()),
+        };
+        if still_marked {
+            existing.insert((parent.get(), ring.kind));
+        } else {
+            commands.entity(ring_entity).despawn_recursive();
+        }
+    }
+
+    let wanted = hovered
+        .iter()
+        .map(|entity| (entity, HighlightKind::Hover))
+        .chain(
+            selected
+                .iter()
+                .map(|entity| (entity, HighlightKind::Select)),
+        );
+    for (entity, kind) in wanted {
+        if existing.contains(&(entity, kind)) {
+            continue;
+        }
+        let (mesh, material) = match kind {
+            HighlightKind::Hover => (assets.hover_mesh.clone(), assets.hover_material.clone()),
+            HighlightKind::Select => (assets.select_mesh.clone(), assets.select_material.clone()),
+        };
+        let ring = commands
+            .spawn(PbrBundle {
+                mesh,
+                material,
+                ..default()
+            })
+            .insert(HighlightRing { kind })
+            .insert(NotShadowCaster)
+            .insert(Name::new("HighlightRing"))
+            .id();
+        commands.add(move |world: &mut World| {
+            // The highlighted entity may have been despawned this frame.
+            if let Some(mut entity) = world.get_entity_mut(entity) {
+                entity.add_child(ring);
+            } else {
+                world.despawn(ring);
+            }
+        });
+    }
+}
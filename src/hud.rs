@@ -0,0 +1,494 @@
+use crate::components::*;
+use crate::enemies::*;
+use crate::health::*;
+use crate::resources::*;
+use crate::selection::*;
+use crate::target::*;
+use crate::tower::*;
+use crate::waves::*;
+
+use bevy::prelude::*;
+
+pub const PANEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+pub const BUTTON_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+pub const BUTTON_HOVERED_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+pub const BUTTON_PRESSED_COLOR: Color = Color::rgb(0.35, 0.6, 0.35);
+const BOSS_BAR_WIDTH: f32 = 400.0;
+
+// Whether the cursor is over a HUD element, in which case clicks shouldn't reach the world.
+#[derive(Debug, Resource, Default, Reflect)]
+#[reflect(Resource)]
+pub struct PointerOverUi(pub bool);
+
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Component)]
+pub enum HudText {
+    #[default]
+    Gold,
+    Lives,
+    Wave,
+    NextWave,
+}
+
+// Group commands which apply to every selected tower.
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Component)]
+pub enum HudButton {
+    #[default]
+    Upgrade,
+    Sell,
+    CycleTargeting,
+    // Cycles between shooting at ground targets, air targets or both.
+    CycleHits,
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct SelectionPanel;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct SelectionInfoText;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct TowerButtons;
+
+// Health bar for the current boss, hidden while there isn't one.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct BossBar;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct BossBarFill;
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct BossBarText;
+
+pub struct HudPlugin {}
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointerOverUi>()
+            .register_type::<HudText>()
+            .register_type::<HudButton>()
+            .register_type::<SelectionPanel>()
+            .register_type::<SelectionInfoText>()
+            .register_type::<TowerButtons>()
+            .register_type::<BossBar>()
+            .register_type::<BossBarFill>()
+            .register_type::<BossBarText>()
+            .insert_resource(PointerOverUi::default())
+            .add_startup_system(spawn_hud)
+            .add_systems((
+                update_pointer_over_ui,
+                update_hud_text,
+                update_selection_panel,
+                update_boss_bar,
+                handle_hud_buttons,
+            ));
+    }
+}
+
+pub fn text_style(assets: &GameAssets, font_size: f32) -> TextStyle {
+    TextStyle {
+        font: assets.font.clone(),
+        font_size,
+        color: Color::WHITE,
+    }
+}
+
+fn spawn_hud(mut commands: Commands, assets: Res<GameAssets>) {
+    // Top bar with the player's resources and wave progress.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(0.0),
+                    top: Val::Px(0.0),
+                    ..default()
+                },
+                size: Size::width(Val::Percent(100.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                gap: Size::width(Val::Px(24.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        })
+        .insert(Interaction::default())
+        .insert(Name::new("HudBar"))
+        .with_children(|bar| {
+            for hud_text in [
+                HudText::Gold,
+                HudText::Lives,
+                HudText::Wave,
+                HudText::NextWave,
+            ] {
+                bar.spawn(TextBundle::from_section("", text_style(&assets, 20.0)))
+                    .insert(hud_text);
+            }
+        });
+
+    // Boss health, centered below the top bar. Not interactive, so it doesn't block clicks.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(0.0),
+                    top: Val::Px(48.0),
+                    ..default()
+                },
+                size: Size::width(Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(BossBar)
+        .insert(Name::new("BossBar"))
+        .with_children(|container| {
+            container
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(6.0)),
+                        gap: Size::height(Val::Px(4.0)),
+                        ..default()
+                    },
+                    background_color: PANEL_COLOR.into(),
+                    ..default()
+                })
+                .with_children(|panel| {
+                    panel
+                        .spawn(TextBundle::from_section("", text_style(&assets, 18.0)))
+                        .insert(BossBarText);
+                    panel
+                        .spawn(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(BOSS_BAR_WIDTH), Val::Px(14.0)),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.3, 0.0, 0.0).into(),
+                            ..default()
+                        })
+                        .with_children(|bar| {
+                            bar.spawn(NodeBundle {
+                                style: Style {
+                                    size: Size::height(Val::Percent(100.0)),
+                                    ..default()
+                                },
+                                background_color: Color::rgb(0.8, 0.1, 0.1).into(),
+                                ..default()
+                            })
+                            .insert(BossBarFill);
+                        });
+                });
+        });
+
+    // Info panel for the current selection.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    bottom: Val::Px(8.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                gap: Size::height(Val::Px(8.0)),
+                min_size: Size::width(Val::Px(220.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(Interaction::default())
+        .insert(SelectionPanel)
+        .insert(Name::new("SelectionPanel"))
+        .with_children(|panel| {
+            panel
+                .spawn(TextBundle::from_section("", text_style(&assets, 18.0)))
+                .insert(SelectionInfoText);
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        gap: Size::height(Val::Px(4.0)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(TowerButtons)
+                .with_children(|buttons| {
+                    for button in [
+                        HudButton::Upgrade,
+                        HudButton::Sell,
+                        HudButton::CycleTargeting,
+                        HudButton::CycleHits,
+                    ] {
+                        buttons
+                            .spawn(ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::all(Val::Px(4.0)),
+                                    justify_content: JustifyContent::Center,
+                                    ..default()
+                                },
+                                background_color: BUTTON_COLOR.into(),
+                                ..default()
+                            })
+                            .insert(button)
+                            .with_children(|button| {
+                                button
+                                    .spawn(TextBundle::from_section("", text_style(&assets, 16.0)));
+                            });
+                    }
+                });
+        });
+}
+
+fn update_pointer_over_ui(
+    interactions: Query<&Interaction, With<Node>>,
+    mut pointer_over_ui: ResMut<PointerOverUi>,
+) {
+    let over = interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if pointer_over_ui.0 != over {
+        pointer_over_ui.0 = over;
+    }
+}
+
+fn update_hud_text(
+    stats: Res<PlayerStats>,
+    waves: Res<Waves>,
+    mut texts: Query<(&mut Text, &HudText)>,
+) {
+    for (mut text, hud_text) in &mut texts {
+        text.sections[0].value = match hud_text {
+            HudText::Gold => format!("Gold: {}", stats.gold),
+            HudText::Lives => format!("Lives: {}", stats.lives),
+            HudText::Wave => format!("Wave: {}/{}", waves.started, waves.total()),
+            HudText::NextWave => match waves.time_to_next_wave() {
+                Some(seconds) => format!("Next wave: {:.0}s", seconds.ceil()),
+                None => String::new(),
+            },
+        };
+    }
+}
+
+fn describe_tower(tower: &Tower) -> String {
+    format!(
+        "Tower (level {})\nDamage: {:.1}\nShots/s: {:.1}\nTargeting: {:?}\nHits: {:?}\nKills: {}",
+        tower.level,
+        tower.damage,
+        1.0 / tower.shooting_timer.duration().as_secs_f32(),
+        tower.targeting,
+        tower.hits,
+        tower.kills,
+    )
+}
+
+fn describe_target(health: &Health, shield: Option<&Shield>, velocity: &Velocity) -> String {
+    let mut description = format!("Target\nHealth: {:.1}/{:.1}", health.val, health.max);
+    if let Some(shield) = shield {
+        description += &format!("\nShield: {:.1}/{:.1}", shield.val, shield.max);
+    }
+    description + &format!("\nSpeed: {:.2}", velocity.val.length())
+}
+
+fn update_selection_panel(
+    selection: Res<Selection>,
+    towers: Query<&Tower>,
+    targets: Query<(&Health, Option<&Shield>, &Velocity), With<Target>>,
+    mut panel: Query<&mut Visibility, With<SelectionPanel>>,
+    mut info: Query<&mut Text, With<SelectionInfoText>>,
+    mut tower_buttons: Query<&mut Style, With<TowerButtons>>,
+    buttons: Query<(&HudButton, &Children)>,
+    mut labels: Query<&mut Text, Without<SelectionInfoText>>,
+) {
+    let mut visibility = panel.single_mut();
+    let Some(primary) = selection.primary() else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+
+    let mut description = if let Ok(tower) = towers.get(primary) {
+        describe_tower(tower)
+    } else if let Ok((health, shield, velocity)) = targets.get(primary) {
+        describe_target(health, shield, velocity)
+    } else {
+        String::from("Unknown")
+    };
+    if selection.len() > 1 {
+        description = format!("{} selected\n{}", selection.len(), description);
+    }
+    info.single_mut().sections[0].value = description;
+
+    let selected_towers: Vec<&Tower> = selection
+        .iter()
+        .filter_map(|entity| towers.get(entity).ok())
+        .collect();
+    tower_buttons.single_mut().display = if selected_towers.is_empty() {
+        Display::None
+    } else {
+        Display::Flex
+    };
+
+    let upgrade_cost: u32 = selected_towers.iter().map(|t| t.upgrade_cost()).sum();
+    let sell_price: u32 = selected_towers.iter().map(|t| t.sell_price()).sum();
+    for (button, children) in &buttons {
+        let Ok(mut label) = labels.get_mut(children[0]) else {
+            continue;
+        };
+        label.sections[0].value = match button {
+            HudButton::Upgrade => format!("Upgrade ({upgrade_cost}g)"),
+            HudButton::Sell => format!("Sell (+{sell_price}g)"),
+            HudButton::CycleTargeting => "Change targeting".to_string(),
+            HudButton::CycleHits => "Change what it hits".to_string(),
+        };
+    }
+}
+
+// Shows the boss with the least health left when several are alive.
+fn update_boss_bar(
+    bosses: Query<(&Health, Option<&Shield>, &Enemy), (With<Boss>, Without<Dead>)>,
+    mut bar: Query<&mut Visibility, With<BossBar>>,
+    mut fill: Query<&mut Style, With<BossBarFill>>,
+    mut text: Query<&mut Text, With<BossBarText>>,
+) {
+    let mut visibility = bar.single_mut();
+    let Some((health, shield, enemy)) = bosses
+        .iter()
+        .min_by(|(a, ..), (b, ..)| a.fraction().total_cmp(&b.fraction()))
+    else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+
+    fill.single_mut().size.width = Val::Percent(100.0 * health.fraction());
+    let mut label = format!("{:?}: {:.0}/{:.0}", enemy.kind, health.val, health.max);
+    if let Some(shield) = shield.filter(|shield| shield.val > 0.0) {
+        label += &format!(" (shield {:.0})", shield.val);
+    }
+    text.single_mut().sections[0].value = label;
+}
+
+// The tower whose settings the others follow when cycling them: the primary selection if it's a
+// tower, otherwise the first selected tower.
+fn lead_tower<'a>(selection: &Selection, towers: &'a Query<&mut Tower>) -> Option<&'a Tower> {
+    selection
+        .primary()
+        .into_iter()
+        .chain(selection.iter())
+        .find_map(|entity| towers.get(entity).ok())
+}
+
+// UI interaction doesn't depend on game time, so these work while paused.
+fn handle_hud_buttons(
+    mut commands: Commands,
+    mut buttons: Query<(&Interaction, &HudButton, &mut BackgroundColor), Changed<Interaction>>,
+    selection: Res<Selection>,
+    mut towers: Query<&mut Tower>,
+    mut stats: ResMut<PlayerStats>,
+) {
+    for (interaction, button, mut color) in &mut buttons {
+        *color = match interaction {
+            Interaction::Clicked => BUTTON_PRESSED_COLOR,
+            Interaction::Hovered => BUTTON_HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            HudButton::Upgrade => {
+                for entity in selection.iter() {
+                    let Ok(mut tower) = towers.get_mut(entity) else {
+                        continue;
+                    };
+                    let cost = tower.upgrade_cost();
+                    if stats.gold < cost {
+                        continue;
+                    }
+                    stats.gold -= cost;
+                    tower.upgrade();
+                }
+            }
+            HudButton::Sell => {
+                for entity in selection.iter() {
+                    let Ok(tower) = towers.get(entity) else {
+                        continue;
+                    };
+                    stats.gold += tower.sell_price();
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            HudButton::CycleTargeting => {
+                // Move every selected tower to the mode after the lead tower's.
+                let Some(mode) =
+                    lead_tower(&selection, &towers).map(|tower| tower.targeting.next())
+                else {
+                    continue;
+                };
+                for entity in selection.iter() {
+                    if let Ok(mut tower) = towers.get_mut(entity) {
+                        tower.targeting = mode;
+                    }
+                }
+            }
+            HudButton::CycleHits => {
+                let Some(hits) = lead_tower(&selection, &towers).map(|tower| tower.hits.next())
+                else {
+                    continue;
+                };
+                for entity in selection.iter() {
+                    if let Ok(mut tower) = towers.get_mut(entity) {
+                        tower.hits = hits;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn cycling_follows_the_first_tower_when_the_primary_isnt_one() {
+        let mut app = TestApp::default();
+        app.init_resource::<Selection>()
+            .insert_resource(PlayerStats::default())
+            .add_system(handle_hud_buttons);
+        let target = app.world.spawn_empty().id();
+        let tower = app.world.spawn(Tower::new(1.0, Vec3::Y)).id();
+        app.world.resource_mut::<Selection>().set([target, tower]);
+        let targeting = Tower::new(1.0, Vec3::Y).targeting;
+        app.world.spawn((
+            Interaction::Clicked,
+            HudButton::CycleTargeting,
+            BackgroundColor::default(),
+        ));
+        app.update();
+        assert_eq!(
+            app.world.get::<Tower>(tower).unwrap().targeting,
+            targeting.next()
+        );
+    }
+}
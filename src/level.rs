@@ -0,0 +1,461 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::collision::*;
+use crate::enemies::*;
+use crate::nav::*;
+use crate::resources::*;
+use crate::terrain::*;
+use crate::tower::*;
+use crate::waves::*;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use bevy_rapier3d::prelude::RigidBody;
+use serde::{Deserialize, Serialize};
+
+// Played when no level is picked on the command line. Relative to the assets folder.
+pub const DEFAULT_LEVEL: &str = "levels/default.level.ron";
+
+// Square ground centered on the origin.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerrainDefinition {
+    pub size: f32,
+    pub color: Color,
+    // Grayscale image, relative to the assets folder. Flat, if there is none.
+    #[serde(default)]
+    pub heightmap: Option<String>,
+    // Height of the white parts of the heightmap.
+    #[serde(default)]
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightDefinition {
+    pub position: Vec3,
+    pub intensity: f32,
+    #[serde(default)]
+    pub shadows: bool,
+}
+
+// Decoration which doesn't affect play.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropDefinition {
+    // Scene to spawn, relative to the assets folder.
+    pub model: String,
+    pub position: Vec3,
+    // Radians around the y axis.
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default = "default_prop_scale")]
+    pub scale: f32,
+    // Props without one don't block towers' line of sight.
+    #[serde(default)]
+    pub collider: Option<ColliderShape>,
+}
+
+fn default_prop_scale() -> f32 {
+    1.0
+}
+
+// Everything needed to set up a level, loaded from a `.level.ron` file. Heights are above the
+// terrain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "16ffb87e-80ab-4472-b50f-7e2544be2d62"]
+pub struct LevelAsset {
+    pub terrain: TerrainDefinition,
+    pub lights: Vec<LightDefinition>,
+    pub route: Route,
+    // Where the player may build towers. Anywhere, if empty.
+    #[serde(default)]
+    pub buildable: Vec<BuildableArea>,
+    // Towers already standing when the level starts.
+    #[serde(default)]
+    pub towers: Vec<Vec3>,
+    #[serde(default)]
+    pub props: Vec<PropDefinition>,
+    pub gold: u32,
+    pub lives: u32,
+    pub waves: Vec<Wave>,
+    // Seconds before the first wave, and between the end of one wave and the start of the next.
+    pub first_wave_delay: f32,
+    pub wave_gap: f32,
+    // Read from the terrain's heightmap image by the loader.
+    #[serde(skip)]
+    pub heightmap: Option<Heightmap>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    Invalid(&'static str),
+    Heightmap(HeightmapError),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "failed to access level file: {err}"),
+            LevelError::Parse(err) => write!(f, "failed to parse level: {err}"),
+            LevelError::Serialize(err) => write!(f, "failed to serialize level: {err}"),
+            LevelError::Invalid(reason) => write!(f, "invalid level: {reason}"),
+            LevelError::Heightmap(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<std::io::Error> for LevelError {
+    fn from(err: std::io::Error) -> Self {
+        LevelError::Io(err)
+    }
+}
+
+impl From<HeightmapError> for LevelError {
+    fn from(err: HeightmapError) -> Self {
+        LevelError::Heightmap(err)
+    }
+}
+
+impl From<ron::Error> for LevelError {
+    fn from(err: ron::Error) -> Self {
+        LevelError::Serialize(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for LevelError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        LevelError::Parse(err)
+    }
+}
+
+impl LevelAsset {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LevelError> {
+        let level: LevelAsset = ron::de::from_bytes(bytes)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), LevelError> {
+        self.validate()?;
+        let config = ron::ser::PrettyConfig::default().struct_names(false);
+        let contents = ron::ser::to_string_pretty(self, config)?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), LevelError> {
+        if self.terrain.size <= 0.0 {
+            return Err(LevelError::Invalid("terrain size must be positive"));
+        }
+        if self.terrain.height < 0.0 {
+            return Err(LevelError::Invalid("terrain height can't be negative"));
+        }
+        if self.route.goal_radius <= 0.0 {
+            return Err(LevelError::Invalid("goal radius must be positive"));
+        }
+        if self.waves.iter().any(|wave| wave.interval <= 0.0) {
+            return Err(LevelError::Invalid("wave intervals must be positive"));
+        }
+        let half = self.terrain.size / 2.0;
+        let on_terrain = |position: Vec3| position.x.abs() <= half && position.z.abs() <= half;
+        if !on_terrain(self.route.spawn) || !on_terrain(self.route.goal) {
+            return Err(LevelError::Invalid("spawn and goal must be on the terrain"));
+        }
+        Ok(())
+    }
+
+    pub fn terrain(&self) -> Terrain {
+        Terrain {
+            size: self.terrain.size,
+            height: self.terrain.height,
+            heightmap: self.heightmap.clone().unwrap_or_else(Heightmap::flat),
+        }
+    }
+
+    // The route with its heights above the terrain turned into positions in the world.
+    pub fn route_on(&self, terrain: &Terrain) -> Route {
+        let mut route = self.route.clone();
+        route.spawn = terrain.snap(route.spawn);
+        route.goal = terrain.snap(route.goal);
+        for layer in [PathLayer::Ground, PathLayer::Air] {
+            for waypoint in route.path_mut(layer) {
+                *waypoint = terrain.snap(*waypoint);
+            }
+        }
+        route
+    }
+
+    // Grid for ground navigation covering the terrain.
+    pub fn nav_grid(&self) -> NavGrid {
+        let half = Vec2::splat(self.terrain.size / 2.0);
+        NavGrid::new(-half, half, NAV_CELL_SIZE)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let mut level = LevelAsset::parse(bytes)?;
+            if let Some(path) = level.terrain.heightmap.clone() {
+                let bytes = load_context.read_asset_bytes(&path).await?;
+                let extension = Path::new(&path)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or_default();
+                level.heightmap = Some(Heightmap::from_image(&bytes, extension)?);
+            }
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+// Marks entities spawned from the level file, as opposed to during play.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct LevelEntity;
+
+// A light from the level file. Whether it casts shadows also depends on the graphics settings.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct LevelLight {
+    pub shadows: bool,
+}
+
+#[derive(Debug, Resource)]
+pub struct CurrentLevel {
+    // Relative to the assets folder.
+    pub path: String,
+    pub handle: Handle<LevelAsset>,
+    // Set once the level has been spawned, which waits for it to load.
+    pub spawned: bool,
+}
+
+pub struct LevelPlugin {
+    // Level file to play, relative to the assets folder.
+    pub path: String,
+}
+
+impl Default for LevelPlugin {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_LEVEL.to_string(),
+        }
+    }
+}
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<LevelEntity>()
+            .register_type::<LevelLight>()
+            .add_asset::<LevelAsset>()
+            .init_asset_loader::<LevelLoader>()
+            .insert_resource(CurrentLevel {
+                path: self.path.clone(),
+                handle: Handle::default(),
+                spawned: false,
+            })
+            .add_startup_system(load_level)
+            .add_system(spawn_level);
+    }
+}
+
+fn load_level(mut level: ResMut<CurrentLevel>, server: Res<AssetServer>) {
+    level.handle = server.load(level.path.as_str());
+}
+
+// Spawns the level once it has loaded, and again whenever it is modified, e.g. by the editor.
+// Towers built by the player, the player's stats and the waves are left as they are on respawns.
+fn spawn_level(
+    mut commands: Commands,
+    mut level: ResMut<CurrentLevel>,
+    mut events: EventReader<AssetEvent<LevelAsset>>,
+    levels: Res<Assets<LevelAsset>>,
+    level_entities: Query<Entity, With<LevelEntity>>,
+    grid: Option<Res<NavGrid>>,
+    server: Res<AssetServer>,
+    assets: Res<GameAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let modified = events
+        .iter()
+        .any(|event| matches!(event, AssetEvent::Modified { handle } if *handle == level.handle));
+    if level.spawned && !modified {
+        return;
+    }
+    let Some(definition) = levels.get(&level.handle) else {
+        if server.get_load_state(&level.handle) != LoadState::Failed {
+            return;
+        }
+        if level.path == DEFAULT_LEVEL {
+            // Nothing left to fall back on. Stop checking so that this is only reported once.
+            error!("Failed to load the default level {DEFAULT_LEVEL}");
+            level.handle = Handle::default();
+        } else {
+            error!(
+                "Failed to load level {}, playing the default level instead",
+                level.path
+            );
+            level.path = DEFAULT_LEVEL.to_string();
+            level.handle = server.load(DEFAULT_LEVEL);
+        }
+        return;
+    };
+
+    if level.spawned {
+        for entity in &level_entities {
+            commands.entity(entity).despawn_recursive();
+        }
+    } else {
+        commands.insert_resource(PlayerStats {
+            gold: definition.gold,
+            lives: definition.lives,
+        });
+        commands.insert_resource(Waves::new(
+            definition.waves.clone(),
+            definition.first_wave_delay,
+            definition.wave_gap,
+        ));
+    }
+    level.spawned = true;
+
+    let terrain = definition.terrain();
+    commands
+        .spawn(PbrBundle {
+            mesh: meshes.add(terrain.mesh()),
+            material: materials.add(definition.terrain.color.into()),
+            ..default()
+        })
+        .insert(RigidBody::Fixed)
+        .insert(terrain.collider())
+        .insert(member_of(GROUND))
+        .insert(LevelEntity)
+        .insert(Name::new("Ground"));
+
+    for light in &definition.lights {
+        commands
+            .spawn(PointLightBundle {
+                point_light: PointLight {
+                    intensity: light.intensity,
+                    shadows_enabled: light.shadows,
+                    ..default()
+                },
+                transform: Transform::from_translation(light.position),
+                ..default()
+            })
+            .insert(LevelLight {
+                shadows: light.shadows,
+            })
+            .insert(LevelEntity)
+            .insert(Name::new("Light"));
+    }
+
+    for position in &definition.towers {
+        let tower = spawn_tower(
+            &mut commands,
+            &assets,
+            terrain.snap(*position),
+            TargetLayers::default(),
+        );
+        commands.entity(tower).insert(LevelEntity);
+    }
+
+    for prop in &definition.props {
+        let mut entity = commands.spawn(SceneBundle {
+            scene: server.load(prop.model.as_str()),
+            transform: Transform::from_translation(terrain.snap(prop.position))
+                .with_rotation(Quat::from_rotation_y(prop.rotation))
+                .with_scale(Vec3::splat(prop.scale)),
+            ..default()
+        });
+        entity.insert(LevelEntity).insert(Name::new("Prop"));
+        if let Some(shape) = prop.collider {
+            entity
+                .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
+                .with_children(|child_cmd| {
+                    child_cmd
+                        .spawn(shape.collider())
+                        .insert(member_of(PROPS))
+                        .insert(Name::new("Hitbox"));
+                });
+        }
+    }
+
+    // Obstacles are only added to a new grid as they are spawned, so keep the one with every
+    // tower in it unless the terrain was resized.
+    let nav_grid = definition.nav_grid();
+    if grid.is_none_or(|grid| grid.size() != nav_grid.size()) {
+        commands.insert_resource(nav_grid);
+    }
+    commands.insert_resource(definition.route_on(&terrain));
+    commands.insert_resource(terrain);
+    commands.insert_resource(BuildableAreas {
+        areas: definition.buildable.clone(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn default_level_is_valid() {
+        let bytes = std::fs::read(format!("assets/{DEFAULT_LEVEL}")).unwrap();
+        let level = LevelAsset::parse(&bytes).unwrap();
+        assert!(!level.waves.is_empty());
+        assert!(level
+            .towers
+            .iter()
+            .all(|tower| level.buildable.iter().any(|area| area.contains(*tower))));
+    }
+
+    #[test]
+    fn goal_off_terrain_is_rejected() {
+        let bytes = std::fs::read(format!("assets/{DEFAULT_LEVEL}")).unwrap();
+        let mut level = LevelAsset::parse(&bytes).unwrap();
+        level.route.goal.x = level.terrain.size;
+        assert!(matches!(level.validate(), Err(LevelError::Invalid(_))));
+    }
+
+    #[test]
+    fn missing_level_falls_back_to_default() {
+        let mut app = TestApp::with_physics();
+        app.add_asset::<StandardMaterial>()
+            .insert_resource(game_assets())
+            .add_plugin(LevelPlugin {
+                path: "levels/missing.level.ron".to_string(),
+            });
+        // Loading happens in the background.
+        for _ in 0..200 {
+            app.update();
+            if app.world.resource::<CurrentLevel>().spawned {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let level = app.world.resource::<CurrentLevel>();
+        assert_eq!(level.path, DEFAULT_LEVEL);
+        assert!(level.spawned);
+    }
+}
@@ -1,5 +1,31 @@
+// Bevy systems take their dependencies as arguments.
+#![allow(clippy::too_many_arguments)]
+// Query filters get long quickly and aliasing each one hurts readability.
+#![allow(clippy::type_complexity)]
+
+pub mod actions;
 pub mod bullet;
+pub mod camera;
+pub mod collision;
 pub mod components;
+pub mod editor;
+pub mod enemies;
+pub mod flow_field;
+pub mod gap_timer;
+pub mod health;
+pub mod health_bar;
+pub mod highlight;
+pub mod hud;
+pub mod level;
+pub mod nav;
+pub mod picking;
 pub mod resources;
+pub mod selection;
+pub mod settings;
+pub mod steering;
 pub mod target;
+pub mod terrain;
+#[cfg(test)]
+mod test_utils;
 pub mod tower;
+pub mod waves;
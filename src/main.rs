@@ -1,307 +1,414 @@
 #![feature(result_option_inspect)]
 #![feature(option_result_contains)]
+// Bevy systems take their dependencies as arguments.
+#![allow(clippy::too_many_arguments)]
+// Query filters get long quickly and aliasing each one hurts readability.
+#![allow(clippy::type_complexity)]
 
 use std::time::Duration;
 
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use bevy::window::WindowResolution;
 use bevy::{prelude::*, window::PrimaryWindow};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
 use bevy_rapier3d::prelude::*;
 
-use bevy_tutorial::{bullet::*, components::*, resources::*, target::*, tower::*};
+use bevy_tutorial::{
+    actions::*, bullet::*, camera::*, components::*, editor::*, enemies::*, gap_timer::*,
+    health::*, health_bar::*, highlight::*, hud::*, level::*, nav::*, picking::*, resources::*,
+    selection::*, settings::*, steering::*, target::*, tower::*, waves::*,
+};
 use derivative::Derivative;
 
-pub const HEIGHT: f32 = 720.0;
-pub const WIDTH: f32 = 1280.0;
-pub const DEFAULT_DEBOUNCE: GapTimer = GapTimer::new(Duration::from_millis(200));
+// Max time between clicks on the same entity to count as a double click.
+pub const DOUBLE_CLICK_GAP: GapTimer = GapTimer::new(Duration::from_millis(300));
+// Cursor movement, in pixels, before a press is treated as a box selection.
+pub const DRAG_THRESHOLD: f32 = 5.0;
 
-// Timer used to check for a specific gap in time.
-// Should only be ticked by a single system.
-#[derive(Debug, Default, Reflect)]
-pub struct GapTimer {
-    pub last: Duration,
-    pub gap: Duration,
+#[derive(Derivative, Resource, Reflect)]
+#[derivative(Debug, Default)]
+#[reflect(Resource)]
+pub struct SelectionInput {
+    // Where the current press started, in window coordinates.
+    pub drag_start: Option<Vec2>,
+    #[derivative(Default(value = "DOUBLE_CLICK_GAP"))]
+    pub double_click: GapTimer,
+    pub last_clicked: Option<Entity>,
 }
 
-impl GapTimer {
-    pub const fn new(gap: Duration) -> GapTimer {
-        GapTimer {
-            last: Duration::ZERO,
-            gap,
+impl SelectionInput {
+    // Records a click on `clicked`, at `now`. Returns whether it's the second click on the same
+    // entity within `DOUBLE_CLICK_GAP` of the first.
+    fn click(&mut self, clicked: Option<Entity>, now: Duration) -> bool {
+        let within_gap = self.double_click.tick_at(now) == 0;
+        let double_clicked = within_gap && clicked.is_some() && self.last_clicked == clicked;
+        // Time the gap from this click, rather than from wherever the timer was in its cycle.
+        self.double_click.reset_at(now);
+        if double_clicked {
+            // Start over so that a third click isn't also a double click.
+            self.double_click.expire();
         }
+        self.last_clicked = clicked;
+        double_clicked
     }
+}
 
-    // Returns the number of ticks which have passed since the last full tick. If at least 1 tick
-    // has passed, resets the timer.
-    pub fn tick(&mut self, time: Duration) -> u32 {
-        let diff = time - self.last;
-        if diff < self.gap {
-            return 0;
+// UI node showing the box selection rectangle.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct DragBox;
+
+// The level to play, from `--level <path>` relative to the assets folder.
+fn level_path() -> String {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--level=") {
+            return path.to_string();
+        }
+        if arg == "--level" {
+            match args.next() {
+                Some(path) => return path,
+                None => eprintln!("--level needs a path, playing the default level"),
+            }
         }
-        self.last = time;
-        (diff.as_nanos() / self.gap.as_nanos()) as u32
     }
-}
-
-#[derive(Debug, Resource, Default, Reflect)]
-#[reflect(Resource)]
-pub struct MousedOverBlinker {
-    pub entity: Option<Entity>,
-    pub original_visibility: Visibility,
-    pub timer: GapTimer,
-}
-
-#[derive(Debug, Resource, Default, Reflect)]
-#[reflect(Resource)]
-pub struct MousedOverEntity {
-    pub entity: Option<Entity>,
-}
-
-#[derive(Derivative, Resource, Reflect)]
-#[derivative(Debug, Default)]
-#[reflect(Resource)]
-pub struct SelectedEntity {
-    pub entity: Option<Entity>,
-    #[derivative(Default(value = "DEFAULT_DEBOUNCE"))]
-    pub debounce: GapTimer,
-    pub blinker: GapTimer,
-    pub original_visibility: Visibility,
-}
-
-#[derive(Derivative, Resource, Reflect)]
-#[derivative(Debug, Default)]
-#[reflect(Resource)]
-pub struct SpacebarTimer {
-    #[derivative(Default(value = "DEFAULT_DEBOUNCE"))]
-    pub debounce: GapTimer,
+    DEFAULT_LEVEL.to_string()
 }
 
 fn main() {
+    let mut saved_settings = SavedSettings::from_file();
+    let settings = saved_settings.with_args(std::env::args().skip(1));
+    let mut window = Window {
+        title: "Bevy Tower Defense".to_string(),
+        resizable: true,
+        ..default()
+    };
+    settings.apply_to_window(&mut window);
+
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                resolution: WindowResolution::new(WIDTH, HEIGHT),
-                title: "Bevy Tower Defense".to_string(),
-                resizable: false,
-                ..default()
-            }),
+            primary_window: Some(window),
             ..default()
         }))
+        // Debug overlays are always added, and switched on and off by `SettingsPlugin`.
         .add_plugin(LogDiagnosticsPlugin::default())
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(DebugLinesPlugin::default())
         // Rapier
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugin(RapierDebugRenderPlugin::default())
         .register_type::<RigidBody>()
         // Inspector requires that components are `reflect` and `register_type`.
-        .add_plugin(WorldInspectorPlugin::new())
+        .add_plugin(WorldInspectorPlugin::new().run_if(show_inspector))
+        .add_plugin(ActionsPlugin {})
         .add_plugin(ComponentsPlugin {})
         .add_plugin(BulletPlugin {})
+        .add_plugin(EditorPlugin {})
+        .add_plugin(EnemiesPlugin {})
+        .add_plugin(HealthPlugin {})
+        .add_plugin(HealthBarPlugin {})
+        .add_plugin(HighlightPlugin {})
+        .add_plugin(HudPlugin {})
+        .add_plugin(LevelPlugin { path: level_path() })
+        .add_plugin(NavPlugin {})
+        .add_plugin(PickingPlugin {})
+        .add_plugin(RtsCameraPlugin {})
+        .add_plugin(SelectionPlugin {})
+        .insert_resource(settings)
+        .insert_resource(saved_settings)
+        .add_plugin(SettingsPlugin {})
+        .add_plugin(SteeringPlugin {})
         .add_plugin(TargetPlugin {})
         .add_plugin(TowerPlugin {})
-        .register_type::<MousedOverBlinker>()
-        .register_type::<MousedOverEntity>()
-        .register_type::<SelectedEntity>()
-        .register_type::<SpacebarTimer>()
+        .add_plugin(WavesPlugin {})
+        .register_type::<PlayerStats>()
+        .register_type::<Route>()
+        .register_type::<SelectionInput>()
+        .register_type::<DragBox>()
         // Our system.
-        .insert_resource(MousedOverBlinker {
-            timer: GapTimer::new(Duration::from_millis(300)),
-            ..default()
-        })
-        .insert_resource(MousedOverEntity::default())
-        .insert_resource(SelectedEntity {
-            blinker: GapTimer::new(Duration::from_millis(100)),
-            ..default()
-        })
-        .insert_resource(SpacebarTimer::default())
+        .insert_resource(PlayerStats::default())
+        .insert_resource(Route::default())
+        .insert_resource(SelectionInput::default())
         .add_startup_systems((load_assets,).in_base_set(StartupSet::PreStartup))
-        .add_startup_systems((spawn_camera, spawn_basic_scene, display_axes))
+        .add_startup_system(spawn_drag_box)
         .add_systems((
-            camera_control,
-            moused_over_entity,
-            pause,
-            select_moused_over,
-            // Consider only having 1 of these at a time.
-            // blink_moused_over,
-            update_selected_entity,
+            display_axes.run_if(show_axes),
+            display_route.run_if(show_route),
+            pause.run_if(not(editing)),
+            request_build.run_if(not(editing)),
+            select_on_click.before(box_select).run_if(not(editing)),
+            box_select.run_if(not(editing)),
+            select_all_of_type.run_if(not(editing)),
+            highlight_moused_over,
+            update_drag_box,
+            focus_selected,
         ))
         .run()
 }
 
-fn spawn_basic_scene(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    assets: Res<GameAssets>,
-) {
-    commands
-        .spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Plane {
-                size: 5.0,
-                subdivisions: 0,
-            })),
-            material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
-            ..default()
-        })
-        .insert(Name::new("Ground"));
+fn pause(actions: Res<ActionState>, mut time: ResMut<Time>) {
+    if !actions.just_pressed(Action::Pause) {
+        return;
+    }
 
-    commands
-        .spawn(SceneBundle {
-            scene: assets.tower_scene.clone(),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
-            ..default()
-        })
-        .insert(Tower {
-            shooting_timer: Timer::from_seconds(0.1, TimerMode::Repeating),
-            bullet_spawn_offset: Vec3::new(0.0, 1.4, 0.0),
-        })
-        .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
-        .with_children(|child_cmd| {
-            child_cmd
-                .spawn(Collider::cylinder(0.7, 0.6))
-                .insert(Transform::from_xyz(0.0, 0.7, 0.0))
-                .insert(Name::new("Hitbox"));
-        })
-        .insert(Name::new("Tower"));
+    if time.is_paused() {
+        time.unpause();
+    } else {
+        time.pause();
+    }
+}
 
-    commands
-        .spawn(PointLightBundle {
-            point_light: PointLight {
-                intensity: 1500.0,
-                shadows_enabled: true,
-                ..default()
-            },
-            transform: Transform::from_xyz(4.0, 8.0, 4.0),
-            ..default()
-        })
-        .insert(Name::new("Light"));
+// A press and release without moving the cursor past `DRAG_THRESHOLD` is a click. Dragging is
+// handled by `box_select`.
+fn select_on_click(
+    actions: Res<ActionState>,
+    primary_query: Query<&Window, With<PrimaryWindow>>,
+    moused_over_entity: Res<MousedOverEntity>,
+    mut selection_input: ResMut<SelectionInput>,
+    mut selection: ResMut<Selection>,
+    towers: Query<Entity, With<Tower>>,
+    targets: Query<Entity, With<Target>>,
+    time: Res<Time>,
+) {
+    if !actions.just_released(Action::Select) {
+        return;
+    }
+    let Some(cursor) = primary_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
+    let Some(start) = selection_input.drag_start else {
+        return;
+    };
+    if start.distance(cursor) >= DRAG_THRESHOLD {
+        return;
+    }
 
-    let target_speed_factor = 0.5;
-    let hitbox = 0.2; // DO NOT SUBMIT - remove for rapier
-    for location in [
-        Transform::from_xyz(-1.0, 0.45, 1.5),
-        Transform::from_xyz(-2.0, 0.45, 1.5),
-    ] {
-        commands
-            .spawn(SceneBundle {
-                scene: assets.target_scene.clone(),
-                transform: location,
-                ..default()
-            })
-            .insert(TargetBundle::new(
-                10.0,
-                Vec3::X * target_speed_factor,
-                hitbox,
-            ))
-            .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
-            .with_children(|child_cmd| {
-                child_cmd
-                    .spawn(Collider::ball(0.4))
-                    .insert(Transform::from_xyz(0.0, 0.0, 0.0))
-                    .insert(Name::new("Hitbox"));
-            })
-            .insert(Name::new("Target"));
+    // Use raw time so that we can select entities when time is paused.
+    let clicked = moused_over_entity.entity;
+    let double_clicked = selection_input.click(clicked, time.raw_elapsed());
+
+    match clicked {
+        Some(entity) if double_clicked => {
+            select_all_like(entity, &towers, &targets, &mut selection)
+        }
+        Some(entity) if actions.pressed(Action::AddToSelection) => selection.toggle(entity),
+        // Clicking on nothing while adding to the selection keeps the selection.
+        None if actions.pressed(Action::AddToSelection) => {}
+        Some(entity) => selection.set([entity]),
+        None => selection.clear(),
     }
 }
 
-fn spawn_camera(mut commands: Commands) {
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
+// Build a tower under the cursor. The tower snaps down onto the terrain, so pointing at something
+// standing on it works too.
+fn request_build(
+    actions: Res<ActionState>,
+    cursor: Res<CursorWorld>,
+    pointer_over_ui: Res<PointerOverUi>,
+    mut build_events: EventWriter<BuildTower>,
+) {
+    if !actions.just_pressed(Action::Build) || pointer_over_ui.0 {
+        return;
+    }
+    let Some(position) = cursor.position() else {
+        return;
+    };
+    // Towers shoot at everything until the player restricts them from the HUD.
+    build_events.send(BuildTower {
+        position,
+        hits: TargetLayers::default(),
     });
 }
 
-fn camera_control(
-    keyboard: Res<Input<KeyCode>>,
-    mut camera_query: Query<&mut Transform, With<Camera3d>>,
-    time: Res<Time>,
+// Tracks where each press starts. Dragging a rectangle selects every tower whose screen position
+// falls inside it.
+fn box_select(
+    actions: Res<ActionState>,
+    primary_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&GlobalTransform, &Camera)>,
+    towers: Query<(Entity, &GlobalTransform), With<Tower>>,
+    pointer_over_ui: Res<PointerOverUi>,
+    mut selection_input: ResMut<SelectionInput>,
+    mut selection: ResMut<Selection>,
 ) {
-    let mut camera = camera_query.single_mut();
-
-    let mut forward = camera.forward();
-    forward.y = 0.0; // Remove tilt so we will move parallel to the ground.
-    forward = forward.normalize();
-    let mut left = camera.left();
-    left.y = 0.0; // Remove tilt so we will move parallel to the ground.
-    left = left.normalize();
-
-    let speed = 4.0;
-    let rotate_speed = 0.5;
+    let Some(cursor) = primary_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
 
-    // Use raw time so that we can pause time and still move the camera.
-    if keyboard.pressed(KeyCode::W) {
-        camera.translation += forward * time.raw_delta_seconds() * speed;
-    }
-    if keyboard.pressed(KeyCode::S) {
-        camera.translation -= forward * time.raw_delta_seconds() * speed;
+    if actions.just_pressed(Action::Select) {
+        // Presses on the HUD are handled by the HUD.
+        selection_input.drag_start = (!pointer_over_ui.0).then_some(cursor);
     }
-    if keyboard.pressed(KeyCode::A) {
-        camera.translation += left * time.raw_delta_seconds() * speed;
-    }
-    if keyboard.pressed(KeyCode::D) {
-        camera.translation -= left * time.raw_delta_seconds() * speed;
+    if !actions.just_released(Action::Select) {
+        return;
     }
-    if keyboard.pressed(KeyCode::Q) {
-        camera.rotate_axis(Vec3::Y, rotate_speed * time.raw_delta_seconds());
+    let Some(start) = selection_input.drag_start.take() else {
+        return;
+    };
+    if start.distance(cursor) < DRAG_THRESHOLD {
+        return;
     }
-    if keyboard.pressed(KeyCode::E) {
-        camera.rotate_axis(Vec3::Y, -rotate_speed * time.raw_delta_seconds());
+
+    let (min, max) = (start.min(cursor), start.max(cursor));
+    let (camera_transform, camera) = camera_query.single();
+    let inside = towers.iter().filter_map(|(entity, transform)| {
+        let position = camera.world_to_viewport(camera_transform, transform.translation())?;
+        (position.cmpge(min).all() && position.cmple(max).all()).then_some(entity)
+    });
+    if actions.pressed(Action::AddToSelection) {
+        selection.extend(inside);
+    } else {
+        selection.set(inside);
     }
 }
 
-fn pause(keyboard: Res<Input<KeyCode>>, mut time: ResMut<Time>, mut timer: ResMut<SpacebarTimer>) {
-    if !keyboard.pressed(KeyCode::Space) {
-        return;
-    }
+fn spawn_drag_box(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            background_color: Color::rgba(0.3, 0.8, 0.3, 0.2).into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(DragBox)
+        .insert(Name::new("DragBox"));
+}
 
-    // Use raw_delta so that we can unpause time.
-    if timer.debounce.tick(time.raw_elapsed()) == 0 {
+fn update_drag_box(
+    actions: Res<ActionState>,
+    selection_input: Res<SelectionInput>,
+    primary_query: Query<&Window, With<PrimaryWindow>>,
+    ui_scale: Res<UiScale>,
+    mut drag_box_query: Query<(&mut Style, &mut Visibility), With<DragBox>>,
+) {
+    let (mut style, mut visibility) = drag_box_query.single_mut();
+    let cursor = primary_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position().map(|cursor| (window, cursor)));
+    let (Some(start), Some((window, cursor)), true) = (
+        selection_input.drag_start,
+        cursor,
+        actions.pressed(Action::Select),
+    ) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    if start.distance(cursor) < DRAG_THRESHOLD {
+        *visibility = Visibility::Hidden;
         return;
     }
 
-    dbg!("pause -- change state");
-    if time.is_paused() {
-        time.unpause();
-    } else {
-        time.pause();
-    }
+    // The cursor is relative to the bottom left, while UI is positioned from the top left, and
+    // scaled along with the window.
+    let scale = ui_scale.scale as f32;
+    let (min, max) = (start.min(cursor) / scale, start.max(cursor) / scale);
+    style.position = UiRect {
+        left: Val::Px(min.x),
+        top: Val::Px(window.height() / scale - max.y),
+        ..default()
+    };
+    style.size = Size::new(Val::Px(max.x - min.x), Val::Px(max.y - min.y));
+    *visibility = Visibility::Visible;
 }
 
-fn select_moused_over(
-    buttons: Res<Input<MouseButton>>,
-    moused_over_entity: ResMut<MousedOverEntity>,
-    mut selected_entity: ResMut<SelectedEntity>,
-    mut visibility_query: Query<&mut Visibility>,
-    time: Res<Time>,
+// Replace the selection with every entity of the same type as the primary selection.
+fn select_all_of_type(
+    actions: Res<ActionState>,
+    towers: Query<Entity, With<Tower>>,
+    targets: Query<Entity, With<Target>>,
+    mut selection: ResMut<Selection>,
 ) {
-    if !buttons.pressed(MouseButton::Left) {
+    if !actions.just_pressed(Action::SelectAllOfType) {
         return;
     }
+    if let Some(primary) = selection.primary() {
+        select_all_like(primary, &towers, &targets, &mut selection);
+    }
+}
 
-    // Use raw_delta so that we can select entities when time is paused.
-    if selected_entity.debounce.tick(time.raw_elapsed()) == 0 {
-        return;
+fn select_all_like(
+    entity: Entity,
+    towers: &Query<Entity, With<Tower>>,
+    targets: &Query<Entity, With<Target>>,
+    selection: &mut Selection,
+) {
+    // The entity itself stays primary, rather than whichever one the query returns first.
+    if towers.contains(entity) {
+        selection.set([entity]);
+        selection.extend(towers.iter());
+    } else if targets.contains(entity) {
+        selection.set([entity]);
+        selection.extend(targets.iter());
     }
+}
 
-    if selected_entity.entity == moused_over_entity.entity {
-        return;
+// Move the `Hovered` marker along as the cursor moves between entities.
+fn highlight_moused_over(mut commands: Commands, mut hover_events: EventReader<HoverEvent>) {
+    for event in hover_events.iter() {
+        match *event {
+            HoverEvent::Entered(entity) => insert_if_exists(&mut commands, entity, Hovered),
+            HoverEvent::Exited(entity) => {
+                if let Some(mut entity) = commands.get_entity(entity) {
+                    entity.remove::<Hovered>();
+                }
+            }
+        }
     }
+}
+
+fn focus_selected(
+    actions: Res<ActionState>,
+    settings: Res<RtsCameraSettings>,
+    selection: Res<Selection>,
+    transforms: Query<&GlobalTransform>,
+    mut camera_query: Query<&mut RtsCamera>,
+) {
+    let mut camera = camera_query.single_mut();
 
-    if let Some(entity) = selected_entity.entity {
-        // Restore entity before override.
-        if let Ok(mut visibility) = visibility_query.get_mut(entity) {
-            *visibility = selected_entity.original_visibility;
-        } else {
-            // This happens if the target is despawned.
+    // Stop following once the entity is deselected.
+    if let Some(entity) = camera.follow {
+        if !selection.contains(entity) {
+            camera.follow = None;
         }
     }
 
-    selected_entity.entity = moused_over_entity.entity;
-    dbg!(&selected_entity.entity);
+    let positions: Vec<Vec3> = selection
+        .iter()
+        .filter_map(|entity| transforms.get(entity).ok())
+        .map(|transform| transform.translation())
+        .collect();
+    // Back off far enough to keep the whole selection in view.
+    let Some((center, distance)) = settings.framing(&positions) else {
+        return;
+    };
+
+    if actions.just_pressed(Action::Focus) {
+        camera.focus_on(center, distance);
+    }
+    if actions.just_pressed(Action::ToggleFollow) {
+        if camera.follow.is_some() {
+            camera.follow = None;
+        } else if let Some((primary, transform)) = selection
+            .primary()
+            .and_then(|primary| Some((primary, transforms.get(primary).ok()?)))
+        {
+            camera.focus_on(transform.translation(), distance);
+            camera.follow = Some(primary);
+        }
+    }
 }
 
 fn load_assets(mut commands: Commands, assets: Res<AssetServer>) {
@@ -309,10 +416,11 @@ fn load_assets(mut commands: Commands, assets: Res<AssetServer>) {
         tower_base_scene: assets.load("TowerBase.glb#Scene0"),
         tower_scene: assets.load("TomatoTower.glb#Scene0"),
         tomato_scene: assets.load("Tomato.glb#Scene0"),
-        target_scene: assets.load("Target.glb#Scene0"),
+        font: assets.load("fonts/DejaVuSans.ttf"),
     });
 }
 
+// Redrawn every frame, so that the axes can be hidden from the settings menu.
 fn display_axes(mut lines: ResMut<DebugLines>) {
     lines.line_colored(
         Vec3::ZERO,
@@ -321,7 +429,7 @@ fn display_axes(mut lines: ResMut<DebugLines>) {
             y: 0.0,
             z: 0.0,
         },
-        1e6,
+        0.0,
         Color::RED,
     );
     lines.line_colored(
@@ -331,7 +439,7 @@ fn display_axes(mut lines: ResMut<DebugLines>) {
             y: 1e6,
             z: 0.0,
         },
-        1e6,
+        0.0,
         Color::GREEN,
     );
     lines.line_colored(
@@ -341,126 +449,53 @@ fn display_axes(mut lines: ResMut<DebugLines>) {
             y: 0.0,
             z: 1e6,
         },
-        1e6,
+        0.0,
         Color::BLUE,
     );
 }
 
-fn moused_over_entity(
-    primary_query: Query<&Window, With<PrimaryWindow>>,
-    camera_query: Query<(&GlobalTransform, &Camera)>,
-    rapier_context: Res<RapierContext>,
-    mut moused_over_entity: ResMut<MousedOverEntity>,
-    parent_query: Query<&Parent>,
-) {
-    // Games typically only have one window (the primary window).
-    // For multi-window applications, you need to use a specific window ID here.
-    let Ok(window) = primary_query.get_single() else {
-        return;
-    };
-    let Some(cursor_position) = window.cursor_position() else {
-        return;
-    };
-
-    // Assume only 1 camera.
-    let (camera_transform, camera) = camera_query.single();
-    let Ray { origin, direction } = camera
-        .viewport_to_world(camera_transform, cursor_position)
-        .unwrap();
-
-    let max_toi = 400.0;
-    let solid = true;
-
-    if let Some((entity, toi)) =
-        rapier_context.cast_ray(origin, direction, max_toi, solid, QueryFilter::new())
-    {
-        // The first collider hit has the entity `entity` and it hit after
-        // the ray travelled a distance equal to `ray_dir * toi`.
-        let hit_point = origin + direction * toi;
-        println!("Entity {:?} hit at point {}", entity, hit_point);
-
-        // Colliders, by my convention, are always the children of the actual entity of interest.
-        let Ok(parent_entity) = parent_query.get(entity) else {
-            return;
-        };
-        moused_over_entity.entity = Some(parent_entity.get());
-    } else {
-        moused_over_entity.entity = None;
+// Redrawn every frame, since the route changes when a level loads or towers are built. Ground
+// targets walk the path around the towers rather than straight between the route's points.
+fn display_route(mut lines: ResMut<DebugLines>, route: Res<Route>, ground_route: Res<GroundRoute>) {
+    for (points, color) in [
+        (ground_route.points.clone(), Color::ORANGE),
+        (
+            std::iter::once(route.spawn)
+                .chain(route.air_path.iter().copied())
+                .chain(std::iter::once(route.goal))
+                .collect(),
+            Color::CYAN,
+        ),
+    ] {
+        for pair in points.windows(2) {
+            lines.line_colored(pair[0], pair[1], 0.0, color);
+        }
     }
 }
 
-// Assumes that mouse entity selection is done via rapier colliders, which are the child of the
-// actual entity we want to blink.
-fn blink_moused_over(
-    moused_over_entity: Res<MousedOverEntity>,
-    mut moused_over_blinker: ResMut<MousedOverBlinker>,
-    mut visibility_query: Query<&mut Visibility>,
-    time: Res<Time>,
-) {
-    let Some(entity) = moused_over_blinker.entity else {
-        let Some(new_entity) = moused_over_entity.entity else {
-            return;
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Store the new blinking target.
-        let Ok(visibility) = visibility_query.get(new_entity) else {return;};
-
-        moused_over_blinker.entity = Some(new_entity);
-        moused_over_blinker.original_visibility = visibility.clone();
-        return;
-    };
-
-    // Intentionally put this timer after the above flow.
-    // Use raw time so that we check selections when time is paused.
-    if moused_over_blinker.timer.tick(time.raw_elapsed()) == 0 {
-        return;
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
     }
 
-    // Blink the entity.
-    let Ok(mut visibility) = visibility_query.get_mut(entity) else {
-        // This happens if the target is despawned.
-        moused_over_blinker.entity = None;
-        return;
-    };
-
-    if *visibility == Visibility::Hidden {
-        *visibility = moused_over_blinker.original_visibility;
-        // If the entity was hidden, it could not have been found via casting.
-        return;
-    }
-
-    // Determine whether to remove the target or not.
-    if Some(entity) == moused_over_entity.entity {
-        *visibility = Visibility::Hidden;
-        return;
-    }
-
-    // The current entity is visible, but we are not moused over it. Reset.
-    *visibility = moused_over_blinker.original_visibility;
-    moused_over_blinker.entity = None;
-}
-
-fn update_selected_entity(
-    mut selected_entity: ResMut<SelectedEntity>,
-    mut visibility_query: Query<&mut Visibility>,
-    time: Res<Time>,
-) {
-    // use raw time since we want to allow entity selection when paused.
-    if selected_entity.blinker.tick(time.raw_elapsed()) == 0 {
-        return;
-    }
-    let Some(entity) = selected_entity.entity else {
-        return;
-    };
-
-    // Blink the entity.
-    let Ok(mut visibility) = visibility_query.get_mut(entity) else {
-        return;
-    };
-
-    if *visibility == Visibility::Hidden {
-        *visibility = selected_entity.original_visibility;
-    } else {
-        *visibility = Visibility::Hidden;
+    #[test]
+    fn double_click_needs_the_same_entity_within_the_gap() {
+        let (a, b) = (Some(Entity::from_raw(1)), Some(Entity::from_raw(2)));
+        let mut input = SelectionInput::default();
+        assert!(!input.click(a, millis(1000)));
+        assert!(!input.click(b, millis(1100)));
+        assert!(input.click(b, millis(1350)));
+        // A third click doesn't make another double click.
+        assert!(!input.click(b, millis(1400)));
+
+        assert!(!input.click(a, millis(5000)));
+        assert!(!input.click(a, millis(5400)));
+        // The gap is timed from the previous click, however long ago the one before was.
+        assert!(input.click(a, millis(5650)));
+        assert!(!input.click(None, millis(6000)));
+        assert!(!input.click(None, millis(6100)));
     }
 }
@@ -0,0 +1,573 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::flow_field::*;
+use crate::resources::*;
+use crate::terrain::*;
+
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy::utils::HashMap;
+
+// Costs of moving to a neighboring cell, scaled so that diagonals stay integers.
+pub(crate) const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+// Width of the square cells of the grid.
+pub const NAV_CELL_SIZE: f32 = 0.25;
+
+const NEIGHBORS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+// Blocks ground targets from walking through the cells within `radius` of the entity.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct NavObstacle {
+    pub radius: f32,
+}
+
+// Grid over the ground plane marking which cells ground targets can walk through. Cells are
+// indexed by `IVec2(x, z)`, starting from `min`.
+#[derive(Debug, Clone, Resource)]
+pub struct NavGrid {
+    min: Vec2,
+    cell_size: f32,
+    size: IVec2,
+    blocked: Vec<bool>,
+}
+
+impl NavGrid {
+    // Grid covering the rectangle from `min` to `max` on the xz plane.
+    pub fn new(min: Vec2, max: Vec2, cell_size: f32) -> Self {
+        let size = ((max - min) / cell_size).ceil().as_ivec2().max(IVec2::ONE);
+        Self {
+            min,
+            cell_size,
+            size,
+            blocked: vec![false; (size.x * size.y) as usize],
+        }
+    }
+
+    pub fn size(&self) -> IVec2 {
+        self.size
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size).all()
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    // Out of bounds cells are always blocked.
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        !self.in_bounds(cell) || self.blocked[self.index(cell)]
+    }
+
+    pub fn set_blocked(&mut self, cell: IVec2, blocked: bool) {
+        if self.in_bounds(cell) {
+            let index = self.index(cell);
+            self.blocked[index] = blocked;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocked.fill(false);
+    }
+
+    pub fn cell_at(&self, position: Vec3) -> Option<IVec2> {
+        let cell = self.unclamped_cell(position);
+        self.in_bounds(cell).then_some(cell)
+    }
+
+    // The cell containing `position`, or the closest one if it is outside the grid.
+    pub fn nearest_cell(&self, position: Vec3) -> IVec2 {
+        self.unclamped_cell(position)
+            .clamp(IVec2::ZERO, self.size - IVec2::ONE)
+    }
+
+    fn unclamped_cell(&self, position: Vec3) -> IVec2 {
+        ((Vec2::new(position.x, position.z) - self.min) / self.cell_size)
+            .floor()
+            .as_ivec2()
+    }
+
+    pub fn cell_center(&self, cell: IVec2, height: f32) -> Vec3 {
+        let center = self.min + (cell.as_vec2() + 0.5) * self.cell_size;
+        Vec3::new(center.x, height, center.y)
+    }
+
+    // The nearest point where cell corners meet, so that obstacles cover whole cells evenly.
+    pub fn snap_to_corner(&self, position: Vec3) -> Vec3 {
+        let corner = ((Vec2::new(position.x, position.z) - self.min) / self.cell_size).round()
+            * self.cell_size
+            + self.min;
+        Vec3::new(corner.x, position.y, corner.y)
+    }
+
+    // Cells whose centers are within `radius` of `position`, including out of bounds cells.
+    pub fn cells_within(&self, position: Vec3, radius: f32) -> Vec<IVec2> {
+        let reach = (radius / self.cell_size).ceil() as i32 + 1;
+        let center = self.unclamped_cell(position);
+        let mut cells = Vec::new();
+        for x in -reach..=reach {
+            for z in -reach..=reach {
+                let cell = center + IVec2::new(x, z);
+                let offset = self.cell_center(cell, position.y) - position;
+                if offset.length() <= radius {
+                    cells.push(cell);
+                }
+            }
+        }
+        cells
+    }
+
+    // Walkable neighbors and the cost of moving to them. Diagonal moves can't cut the corner
+    // of a blocked cell.
+    pub(crate) fn neighbors(&self, cell: IVec2) -> impl Iterator<Item = (IVec2, u32)> + '_ {
+        NEIGHBORS.iter().filter_map(move |offset| {
+            let next = cell + *offset;
+            if self.is_blocked(next) {
+                return None;
+            }
+            if offset.x != 0 && offset.y != 0 {
+                let corners_blocked = self.is_blocked(cell + IVec2::new(offset.x, 0))
+                    || self.is_blocked(cell + IVec2::new(0, offset.y));
+                return (!corners_blocked).then_some((next, DIAGONAL_COST));
+            }
+            Some((next, STRAIGHT_COST))
+        })
+    }
+
+    // Octile distance, which never overestimates the cost with diagonal moves.
+    fn heuristic(from: IVec2, to: IVec2) -> u32 {
+        let delta = (to - from).abs();
+        let (long, short) = (delta.max_element() as u32, delta.min_element() as u32);
+        STRAIGHT_COST * (long - short) + DIAGONAL_COST * short
+    }
+
+    // A* from `start` to `goal`, both included. `start` may be blocked, so that targets standing
+    // where an obstacle was placed can still walk out.
+    pub fn find_path(&self, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+        if !self.in_bounds(start) || self.is_blocked(goal) {
+            return None;
+        }
+        let cells = self.blocked.len();
+        let mut cost = vec![u32::MAX; cells];
+        let mut came_from: Vec<Option<IVec2>> = vec![None; cells];
+        let mut open = BinaryHeap::new();
+        cost[self.index(start)] = 0;
+        open.push(Reverse((Self::heuristic(start, goal), start.x, start.y)));
+
+        while let Some(Reverse((_, x, y))) = open.pop() {
+            let cell = IVec2::new(x, y);
+            if cell == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(previous) = came_from[self.index(current)] {
+                    path.push(previous);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let cell_cost = cost[self.index(cell)];
+            for (next, step) in self.neighbors(cell) {
+                let next_cost = cell_cost + step;
+                let next_index = self.index(next);
+                if next_cost < cost[next_index] {
+                    cost[next_index] = next_cost;
+                    came_from[next_index] = Some(cell);
+                    let estimate = next_cost + Self::heuristic(next, goal);
+                    open.push(Reverse((estimate, next.x, next.y)));
+                }
+            }
+        }
+        None
+    }
+
+    // Whether a path from `start` to `goal` would remain if `cells` were blocked as well.
+    pub fn allows_path_with(&self, cells: &[IVec2], start: IVec2, goal: IVec2) -> bool {
+        self.with_blocked(cells).find_path(start, goal).is_some()
+    }
+
+    // A copy of the grid with `cells` blocked as well.
+    pub fn with_blocked(&self, cells: &[IVec2]) -> NavGrid {
+        let mut grid = self.clone();
+        for cell in cells {
+            grid.set_blocked(*cell, true);
+        }
+        grid
+    }
+
+    // Turns a path of cells into waypoints at `height`, keeping only the cells where the
+    // direction changes. The first cell is dropped since the walker is already in it.
+    pub fn waypoints(&self, path: &[IVec2], height: f32) -> Vec<Vec3> {
+        let mut waypoints = Vec::new();
+        for (i, cell) in path.iter().enumerate().skip(1) {
+            let turns = path
+                .get(i + 1)
+                .is_none_or(|next| *next - *cell != *cell - path[i - 1]);
+            if turns {
+                waypoints.push(self.cell_center(*cell, height));
+            }
+        }
+        waypoints
+    }
+}
+
+impl Default for NavGrid {
+    // Covers the ground plane.
+    fn default() -> Self {
+        NavGrid::new(Vec2::splat(-2.5), Vec2::splat(2.5), NAV_CELL_SIZE)
+    }
+}
+
+// The path ground targets walk from spawn through the route's waypoints to the goal, around
+// obstacles, as the flow fields lead them. Worked out whenever the grid or the route changes, for
+// drawing. The level's `Route` is left as it was authored.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct GroundRoute {
+    // The spawn, the points where the path turns, and the goal.
+    pub points: Vec<Vec3>,
+}
+
+// The cells each obstacle blocks, and how many obstacles block each cell, so that obstacles can
+// be added and removed without rebuilding the grid.
+#[derive(Debug, Default, Resource)]
+struct ObstacleCells {
+    by_obstacle: HashMap<Entity, Vec<IVec2>>,
+    blockers: HashMap<IVec2, u32>,
+    // Size of the grid the cells were counted on. A level with a different size replaces the
+    // grid with an empty one, which every obstacle is added to again.
+    grid_size: Option<IVec2>,
+}
+
+impl ObstacleCells {
+    fn add(&mut self, grid: &mut NavGrid, obstacle: Entity, cells: Vec<IVec2>) {
+        for cell in &cells {
+            *self.blockers.entry(*cell).or_default() += 1;
+            grid.set_blocked(*cell, true);
+        }
+        self.by_obstacle.insert(obstacle, cells);
+    }
+
+    // Unblocks the obstacle's cells, unless other obstacles cover them too.
+    fn remove(&mut self, grid: &mut NavGrid, obstacle: Entity) {
+        for cell in self.by_obstacle.remove(&obstacle).unwrap_or_default() {
+            let Some(blockers) = self.blockers.get_mut(&cell) else {
+                continue;
+            };
+            *blockers -= 1;
+            if *blockers == 0 {
+                self.blockers.remove(&cell);
+                grid.set_blocked(cell, false);
+            }
+        }
+    }
+}
+
+pub struct NavPlugin {}
+
+impl Plugin for NavPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<NavObstacle>()
+            .insert_resource(NavGrid::default())
+            .init_resource::<ObstacleCells>()
+            .init_resource::<GroundRoute>()
+            .init_resource::<Terrain>()
+            // Needs the positions of obstacles spawned this frame.
+            .add_systems(
+                (update_obstacles, update_flow_field)
+                    .chain()
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+// Blocks the cells of obstacles as they are added, and unblocks them as they are removed.
+fn update_obstacles(
+    mut grid: ResMut<NavGrid>,
+    mut cells: ResMut<ObstacleCells>,
+    obstacles: Query<(Entity, &GlobalTransform, &NavObstacle)>,
+    added: Query<Entity, Added<NavObstacle>>,
+    mut removed: RemovedComponents<NavObstacle>,
+) {
+    let obstacle_cells =
+        |grid: &NavGrid,
+         (entity, transform, obstacle): (Entity, &GlobalTransform, &NavObstacle)| {
+            (
+                entity,
+                grid.cells_within(transform.translation(), obstacle.radius),
+            )
+        };
+
+    if cells.grid_size != Some(grid.size()) {
+        *cells = ObstacleCells {
+            grid_size: Some(grid.size()),
+            ..default()
+        };
+        grid.clear();
+        for obstacle in &obstacles {
+            let (entity, blocked) = obstacle_cells(&grid, obstacle);
+            cells.add(&mut grid, entity, blocked);
+        }
+        return;
+    }
+
+    for entity in removed.iter() {
+        cells.remove(&mut grid, entity);
+    }
+    for obstacle in added.iter().filter_map(|entity| obstacles.get(entity).ok()) {
+        let (entity, blocked) = obstacle_cells(&grid, obstacle);
+        cells.add(&mut grid, entity, blocked);
+    }
+}
+
+// Incrementally updates the flow fields for changes to the grid, or starts over if the route's
+// waypoints or goal moved, then follows them from spawn for the `GroundRoute`.
+fn update_flow_field(
+    mut commands: Commands,
+    grid: Res<NavGrid>,
+    route: Res<Route>,
+    terrain: Res<Terrain>,
+    fields: Option<ResMut<RouteFlowFields>>,
+    mut ground_route: ResMut<GroundRoute>,
+) {
+    match fields {
+        Some(mut fields) if fields.follows(&route) => {
+            if !grid.is_changed() && !route.is_changed() {
+                return;
+            }
+            fields.update(&grid);
+            ground_route.follow(&fields, &grid, &route, &terrain);
+        }
+        _ => {
+            let fields = RouteFlowFields::new(&grid, &route);
+            ground_route.follow(&fields, &grid, &route, &terrain);
+            commands.insert_resource(fields);
+        }
+    }
+}
+
+impl GroundRoute {
+    fn follow(
+        &mut self,
+        fields: &RouteFlowFields,
+        grid: &NavGrid,
+        route: &Route,
+        terrain: &Terrain,
+    ) {
+        let Some(cells) = fields.walk(grid, route.spawn) else {
+            warn!("Obstacles block the route from spawn to goal");
+            self.points.clear();
+            return;
+        };
+        // Keep the goal's clearance over the terrain along the way.
+        let clearance = terrain.clearance(route.goal);
+        let mut turns: Vec<Vec3> = grid
+            .waypoints(&cells, clearance)
+            .into_iter()
+            .map(|point| terrain.snap(point))
+            .collect();
+        // The goal's cell, which the goal itself stands in for.
+        turns.pop();
+        self.points = std::iter::once(route.spawn)
+            .chain(turns)
+            .chain(std::iter::once(route.goal))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestApp;
+
+    // Builds a grid with 1x1 cells from lines of text, where '#' is blocked. The first line is
+    // z = 0.
+    fn grid(text: &str) -> NavGrid {
+        let rows: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect();
+        let size = Vec2::new(rows[0].len() as f32, rows.len() as f32);
+        let mut grid = NavGrid::new(Vec2::ZERO, size, 1.0);
+        for (z, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                grid.set_blocked(IVec2::new(x as i32, z as i32), c == '#');
+            }
+        }
+        grid
+    }
+
+    fn cell(x: i32, z: i32) -> IVec2 {
+        IVec2::new(x, z)
+    }
+
+    #[test]
+    fn straight_path_on_empty_grid() {
+        let grid = grid(".....");
+        let path = grid.find_path(cell(0, 0), cell(4, 0)).unwrap();
+        assert_eq!(path, (0..5).map(|x| cell(x, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn path_goes_around_wall() {
+        let grid = grid(
+            "
+            ..#..
+            ..#..
+            .....
+        ",
+        );
+        let path = grid.find_path(cell(0, 0), cell(4, 0)).unwrap();
+        assert!(path.iter().all(|cell| !grid.is_blocked(*cell)));
+        assert!(path.contains(&cell(2, 2)));
+        assert_eq!(path.first(), Some(&cell(0, 0)));
+        assert_eq!(path.last(), Some(&cell(4, 0)));
+    }
+
+    #[test]
+    fn no_path_through_full_wall() {
+        let grid = grid(
+            "
+            ..#..
+            ..#..
+            ..#..
+        ",
+        );
+        assert_eq!(grid.find_path(cell(0, 0), cell(4, 0)), None);
+    }
+
+    #[test]
+    fn diagonals_do_not_cut_corners() {
+        let grid = grid(
+            "
+            .#
+            #.
+        ",
+        );
+        assert_eq!(grid.find_path(cell(0, 0), cell(1, 1)), None);
+    }
+
+    #[test]
+    fn start_may_be_blocked() {
+        let grid = grid("#..");
+        assert!(grid.find_path(cell(0, 0), cell(2, 0)).is_some());
+        assert_eq!(grid.find_path(cell(2, 0), cell(0, 0)), None);
+    }
+
+    #[test]
+    fn placement_which_blocks_route_is_detected() {
+        let grid = grid(
+            "
+            ..#..
+            ..#..
+            .....
+        ",
+        );
+        assert!(grid.allows_path_with(&[cell(0, 2)], cell(0, 0), cell(4, 0)));
+        assert!(!grid.allows_path_with(&[cell(2, 2)], cell(0, 0), cell(4, 0)));
+        // Checking doesn't modify the grid.
+        assert!(!grid.is_blocked(cell(2, 2)));
+    }
+
+    #[test]
+    fn waypoints_keep_only_turns() {
+        let grid = grid(
+            "
+            ...
+            ...
+            ...
+        ",
+        );
+        let path = [cell(0, 0), cell(1, 0), cell(2, 0), cell(2, 1), cell(2, 2)];
+        assert_eq!(
+            grid.waypoints(&path, 0.0),
+            vec![Vec3::new(2.5, 0.0, 0.5), Vec3::new(2.5, 0.0, 2.5)]
+        );
+    }
+
+    #[test]
+    fn cells_and_positions() {
+        let grid = NavGrid::new(Vec2::splat(-1.0), Vec2::splat(1.0), 0.5);
+        assert_eq!(grid.size(), IVec2::new(4, 4));
+        assert_eq!(grid.cell_at(Vec3::new(-0.9, 5.0, 0.9)), Some(cell(0, 3)));
+        assert_eq!(grid.cell_at(Vec3::new(1.5, 0.0, 0.0)), None);
+        assert_eq!(grid.nearest_cell(Vec3::new(1.5, 0.0, -3.0)), cell(3, 0));
+        assert_eq!(
+            grid.snap_to_corner(Vec3::new(0.2, 1.0, -0.3)),
+            Vec3::new(0.0, 1.0, -0.5)
+        );
+        // The four cells around a corner.
+        let mut cells = grid.cells_within(Vec3::ZERO, 0.4);
+        cells.sort_by_key(|cell| (cell.x, cell.y));
+        assert_eq!(cells, vec![cell(1, 1), cell(1, 2), cell(2, 1), cell(2, 2)]);
+    }
+
+    fn obstacle(app: &mut TestApp, position: Vec3) -> Entity {
+        app.world
+            .spawn((
+                TransformBundle::from(Transform::from_translation(position)),
+                NavObstacle { radius: 0.3 },
+            ))
+            .id()
+    }
+
+    #[test]
+    fn obstacles_are_blocked_until_the_last_one_covering_a_cell_goes() {
+        let mut app = TestApp::default();
+        app.add_plugin(TransformPlugin)
+            .add_plugin(NavPlugin {})
+            // Straight from spawn to goal.
+            .insert_resource(Route {
+                ground_path: Vec::new(),
+                ..default()
+            });
+        app.update();
+        let route = app.world.resource::<Route>().clone();
+        // Nothing in the way of the straight line between them.
+        assert_eq!(
+            app.world.resource::<GroundRoute>().points,
+            vec![route.spawn, route.goal]
+        );
+
+        let middle = Vec3::new(0.0, 0.0, route.goal.z);
+        let first = obstacle(&mut app, middle);
+        let second = obstacle(&mut app, middle + Vec3::X * 0.25);
+        app.update();
+        let cell = app.world.resource::<NavGrid>().nearest_cell(middle);
+        assert!(app.world.resource::<NavGrid>().is_blocked(cell));
+        assert!(app.world.resource::<GroundRoute>().points.len() > 2);
+
+        app.world.despawn(first);
+        app.update();
+        assert!(app.world.resource::<NavGrid>().is_blocked(cell));
+
+        app.world.despawn(second);
+        app.update();
+        assert!(!app.world.resource::<NavGrid>().is_blocked(cell));
+        assert_eq!(
+            app.world.resource::<GroundRoute>().points,
+            vec![route.spawn, route.goal]
+        );
+        // The route is as the level authored it.
+        assert_eq!(app.world.resource::<Route>().ground_path, route.ground_path);
+    }
+}
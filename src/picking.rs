@@ -0,0 +1,218 @@
+use crate::camera::*;
+use crate::collision::*;
+use crate::hud::*;
+use crate::resources::*;
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_rapier3d::prelude::{RapierContext, RayIntersection};
+
+// How far the cursor's ray reaches.
+pub const PICK_DISTANCE: f32 = 400.0;
+
+// Entities which the cursor can hover and select. Their colliders may be anywhere below them in
+// the hierarchy, e.g. inside a scene.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Selectable {
+    // When the cursor is over several entities, the highest priority one is picked, and the
+    // closest of those.
+    pub priority: u32,
+}
+
+// Where the cursor points in the world, for placing things and the like.
+#[derive(Debug, Resource, Default, PartialEq, Reflect)]
+#[reflect(Resource)]
+pub struct CursorWorld {
+    // The first collider the cursor's ray hits, and where.
+    pub hit_point: Option<Vec3>,
+    pub hit_normal: Option<Vec3>,
+    pub hit_entity: Option<Entity>,
+    // Where the ray crosses the y = 0 plane, for when it misses everything, e.g. off the edge of
+    // the terrain.
+    pub plane_point: Option<Vec3>,
+}
+
+impl CursorWorld {
+    pub fn position(&self) -> Option<Vec3> {
+        self.hit_point.or(self.plane_point)
+    }
+}
+
+// Sent when the cursor moves onto or off of a `Selectable` entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoverEvent {
+    Entered(Entity),
+    Exited(Entity),
+}
+
+// Collider along the cursor's ray, and the selectable entity it belongs to, if any.
+#[derive(Debug, Clone, Copy)]
+pub struct PickHit {
+    pub toi: f32,
+    pub selectable: Option<(Entity, u32)>,
+}
+
+// Picks the highest priority selectable which is in front of anything that isn't selectable, like
+// the ground. Ties go to the closest.
+pub fn best_pick(hits: &[PickHit]) -> Option<Entity> {
+    let blocked_at = hits
+        .iter()
+        .filter(|hit| hit.selectable.is_none())
+        .map(|hit| hit.toi)
+        .fold(f32::INFINITY, f32::min);
+    hits.iter()
+        .filter(|hit| hit.toi <= blocked_at)
+        .filter_map(|hit| {
+            hit.selectable
+                .map(|(entity, priority)| (entity, priority, hit.toi))
+        })
+        .max_by(|(_, a_priority, a_toi), (_, b_priority, b_toi)| {
+            a_priority
+                .cmp(b_priority)
+                .then_with(|| b_toi.total_cmp(a_toi))
+        })
+        .map(|(entity, ..)| entity)
+}
+
+// The nearest `Selectable` at or above `entity` in the hierarchy.
+fn selectable_ancestor(
+    entity: Entity,
+    selectables: &Query<&Selectable>,
+    parent_query: &Query<&Parent>,
+) -> Option<(Entity, u32)> {
+    std::iter::successors(Some(entity), |entity| {
+        parent_query.get(*entity).ok().map(|parent| parent.get())
+    })
+    .find_map(|entity| {
+        selectables
+            .get(entity)
+            .ok()
+            .map(|selectable| (entity, selectable.priority))
+    })
+}
+
+pub struct PickingPlugin {}
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Selectable>()
+            .register_type::<MousedOverEntity>()
+            .register_type::<CursorWorld>()
+            .init_resource::<MousedOverEntity>()
+            .init_resource::<CursorWorld>()
+            .add_event::<HoverEvent>()
+            .add_system(pick);
+    }
+}
+
+// Nothing in the world is under the cursor while it's over the UI.
+fn pick(
+    pointer_over_ui: Res<PointerOverUi>,
+    primary_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&GlobalTransform, &Camera)>,
+    rapier_context: Res<RapierContext>,
+    selectables: Query<&Selectable>,
+    parent_query: Query<&Parent>,
+    mut moused_over_entity: ResMut<MousedOverEntity>,
+    mut cursor: ResMut<CursorWorld>,
+    mut hover_events: EventWriter<HoverEvent>,
+) {
+    let ray = primary_query
+        .get_single()
+        .ok()
+        .filter(|_| !pointer_over_ui.0)
+        .and_then(|window| window.cursor_position())
+        .zip(camera_query.get_single().ok())
+        .and_then(|(cursor, (camera_transform, camera))| {
+            camera.viewport_to_world(camera_transform, cursor)
+        });
+
+    let mut hits = Vec::new();
+    let mut first_hit = None;
+    if let Some(ray) = ray {
+        rapier_context.intersections_with_ray(
+            ray.origin,
+            ray.direction,
+            PICK_DISTANCE,
+            true,
+            filter_for(BLOCKS_CURSOR),
+            |collider, intersection| {
+                let closer = |(_, first): (_, RayIntersection)| intersection.toi < first.toi;
+                if first_hit.is_none_or(closer) {
+                    first_hit = Some((collider, intersection));
+                }
+                hits.push(PickHit {
+                    toi: intersection.toi,
+                    selectable: selectable_ancestor(collider, &selectables, &parent_query),
+                });
+                true
+            },
+        );
+    }
+    // Only flag the cursor as changed when it points somewhere else.
+    cursor.set_if_neq(CursorWorld {
+        hit_point: first_hit.map(|(_, intersection)| intersection.point),
+        hit_normal: first_hit.map(|(_, intersection)| intersection.normal),
+        hit_entity: first_hit.map(|(collider, _)| collider),
+        plane_point: ray.and_then(|ray| intersect_plane(ray, 0.0)),
+    });
+
+    let entity = best_pick(&hits);
+    if moused_over_entity.entity != entity {
+        if let Some(previous) = moused_over_entity.entity {
+            hover_events.send(HoverEvent::Exited(previous));
+        }
+        if let Some(entity) = entity {
+            hover_events.send(HoverEvent::Entered(entity));
+        }
+        moused_over_entity.entity = entity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(toi: f32, selectable: Option<(u32, u32)>) -> PickHit {
+        PickHit {
+            toi,
+            selectable: selectable.map(|(index, priority)| (Entity::from_raw(index), priority)),
+        }
+    }
+
+    #[test]
+    fn closest_selectable_is_picked() {
+        let hits = [hit(3.0, Some((1, 0))), hit(2.0, Some((2, 0)))];
+        assert_eq!(best_pick(&hits), Some(Entity::from_raw(2)));
+        assert_eq!(best_pick(&[]), None);
+    }
+
+    #[test]
+    fn higher_priority_wins_over_closer() {
+        let hits = [hit(2.0, Some((1, 0))), hit(2.5, Some((2, 1)))];
+        assert_eq!(best_pick(&hits), Some(Entity::from_raw(2)));
+    }
+
+    #[test]
+    fn nothing_is_picked_through_the_ground() {
+        let hits = [
+            hit(5.0, Some((1, 1))),
+            hit(4.0, None),
+            hit(3.0, Some((2, 0))),
+        ];
+        assert_eq!(best_pick(&hits), Some(Entity::from_raw(2)));
+        assert_eq!(best_pick(&hits[..2]), None);
+    }
+
+    #[test]
+    fn cursor_falls_back_to_the_plane() {
+        let mut cursor = CursorWorld {
+            plane_point: Some(Vec3::X),
+            ..default()
+        };
+        assert_eq!(cursor.position(), Some(Vec3::X));
+        cursor.hit_point = Some(Vec3::Y);
+        assert_eq!(cursor.position(), Some(Vec3::Y));
+    }
+}
@@ -1,4 +1,6 @@
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 // Resources.
 #[derive(Resource, Debug)]
@@ -6,5 +8,144 @@ pub struct GameAssets {
     pub tower_base_scene: Handle<Scene>,
     pub tower_scene: Handle<Scene>,
     pub tomato_scene: Handle<Scene>,
-    pub target_scene: Handle<Scene>,
+    pub font: Handle<Font>,
+}
+
+// The selectable entity under the cursor.
+#[derive(Debug, Resource, Default, Reflect)]
+#[reflect(Resource)]
+pub struct MousedOverEntity {
+    pub entity: Option<Entity>,
+}
+
+#[derive(Resource, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct PlayerStats {
+    pub gold: u32,
+    pub lives: u32,
+}
+
+impl Default for PlayerStats {
+    fn default() -> Self {
+        Self {
+            gold: 200,
+            lives: 20,
+        }
+    }
+}
+
+// Which path a target follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect, FromReflect)]
+pub enum PathLayer {
+    #[default]
+    Ground,
+    Air,
+}
+
+// Where targets enter the map, the paths they take, and where they are trying to get to.
+#[derive(Resource, Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+pub struct Route {
+    pub spawn: Vec3,
+    pub goal: Vec3,
+    // Targets within this distance of the goal have reached it.
+    pub goal_radius: f32,
+    // Waypoints between the spawn and the goal, at ground height. Flying targets keep their
+    // altitude above the air path.
+    pub ground_path: Vec<Vec3>,
+    pub air_path: Vec<Vec3>,
+}
+
+impl Route {
+    pub fn path(&self, layer: PathLayer) -> &[Vec3] {
+        match layer {
+            PathLayer::Ground => &self.ground_path,
+            PathLayer::Air => &self.air_path,
+        }
+    }
+
+    pub fn path_mut(&mut self, layer: PathLayer) -> &mut Vec<Vec3> {
+        match layer {
+            PathLayer::Ground => &mut self.ground_path,
+            PathLayer::Air => &mut self.air_path,
+        }
+    }
+
+    // The `index`th point to head for on `path`. The goal follows the last waypoint.
+    pub fn waypoint(&self, path: &[Vec3], index: usize) -> Option<Vec3> {
+        match index.cmp(&path.len()) {
+            std::cmp::Ordering::Less => Some(path[index]),
+            std::cmp::Ordering::Equal => Some(self.goal),
+            std::cmp::Ordering::Greater => None,
+        }
+    }
+
+    // Distance left to travel along `path`, ignoring height, from `position` while heading for
+    // the `index`th waypoint.
+    pub fn remaining_distance(&self, path: &[Vec3], index: usize, position: Vec3) -> f32 {
+        let mut distance = 0.0;
+        let mut from = position;
+        let mut index = index;
+        while let Some(waypoint) = self.waypoint(path, index) {
+            distance += (waypoint - from).xz().length();
+            from = waypoint;
+            index += 1;
+        }
+        distance
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Self {
+            spawn: Vec3::new(-2.5, 0.45, 1.5),
+            goal: Vec3::new(2.5, 0.45, 1.5),
+            goal_radius: 0.2,
+            // Wind around the tower in the middle of the map.
+            ground_path: vec![
+                Vec3::new(-1.5, 0.45, 1.5),
+                Vec3::new(-1.5, 0.45, -1.5),
+                Vec3::new(1.5, 0.45, -1.5),
+                Vec3::new(1.5, 0.45, 1.5),
+            ],
+            // Fly straight over the middle.
+            air_path: vec![Vec3::new(0.0, 0.45, -0.5)],
+        }
+    }
+}
+
+// Rectangle on the ground, from `min` to `max` on the xz plane, where towers may be built.
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct BuildableArea {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl BuildableArea {
+    pub fn contains(&self, position: Vec3) -> bool {
+        let position = position.xz();
+        position.cmpge(self.min).all() && position.cmple(self.max).all()
+    }
+
+    // The same area with `min` and `max` swapped as needed, e.g. after dragging a corner past
+    // the opposite one.
+    pub fn normalized(self) -> Self {
+        Self {
+            min: self.min.min(self.max),
+            max: self.min.max(self.max),
+        }
+    }
+}
+
+// Where towers may be built. Anywhere, if there are no areas.
+#[derive(Resource, Debug, Clone, Default, Reflect)]
+#[reflect(Resource)]
+pub struct BuildableAreas {
+    pub areas: Vec<BuildableArea>,
+}
+
+impl BuildableAreas {
+    pub fn allows(&self, position: Vec3) -> bool {
+        self.areas.is_empty() || self.areas.iter().any(|area| area.contains(position))
+    }
 }
@@ -0,0 +1,172 @@
+use crate::highlight::*;
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+// The set of entities chosen by the player. Systems acting on "the selection" (group upgrades,
+// selling, targeting changes) should read this rather than the `Selected` markers, which are
+// kept in sync for rendering.
+#[derive(Debug, Resource, Default)]
+pub struct Selection {
+    entities: HashSet<Entity>,
+    // The entity shown in single entity views (info panel, camera focus). Always in `entities`.
+    primary: Option<Entity>,
+}
+
+impl Selection {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn primary(&self) -> Option<Entity> {
+        self.primary
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.primary = None;
+    }
+
+    pub fn insert(&mut self, entity: Entity) {
+        self.entities.insert(entity);
+        self.primary.get_or_insert(entity);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        self.entities.remove(&entity);
+        if self.primary == Some(entity) {
+            self.primary = self.entities.iter().next().copied();
+        }
+    }
+
+    // Adds the entity if it isn't selected, otherwise removes it.
+    pub fn toggle(&mut self, entity: Entity) {
+        if self.contains(entity) {
+            self.remove(entity);
+        } else {
+            self.insert(entity);
+        }
+    }
+
+    // Replaces the selection. The first entity becomes the primary.
+    pub fn set(&mut self, entities: impl IntoIterator<Item = Entity>) {
+        self.clear();
+        self.extend(entities);
+    }
+
+    pub fn extend(&mut self, entities: impl IntoIterator<Item = Entity>) {
+        for entity in entities {
+            self.insert(entity);
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(Entity) -> bool) {
+        self.entities.retain(|entity| keep(*entity));
+        if matches!(self.primary, Some(primary) if !self.entities.contains(&primary)) {
+            self.primary = self.entities.iter().next().copied();
+        }
+    }
+}
+
+pub struct SelectionPlugin {}
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Selection::default())
+            .add_system(sync_selected_markers);
+    }
+}
+
+// Drops despawned entities from the selection and mirrors it onto the `Selected` markers.
+fn sync_selected_markers(
+    mut commands: Commands,
+    mut selection: ResMut<Selection>,
+    entities: Query<Entity>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    // Avoid triggering change detection when nothing was despawned.
+    if selection.iter().any(|entity| !entities.contains(entity)) {
+        selection.retain(|entity| entities.contains(entity));
+    }
+
+    if !selection.is_changed() {
+        return;
+    }
+    for entity in &selected {
+        if !selection.contains(entity) {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+    for entity in selection.iter() {
+        if !selected.contains(entity) {
+            insert_if_exists(&mut commands, entity, Selected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn entities(count: u32) -> Vec<Entity> {
+        (0..count).map(Entity::from_raw).collect()
+    }
+
+    #[test]
+    fn first_entity_set_is_primary() {
+        let e = entities(3);
+        let mut selection = Selection::default();
+        selection.set([e[1], e[0], e[2]]);
+        assert_eq!(selection.primary(), Some(e[1]));
+        assert_eq!(selection.len(), 3);
+        // Extending keeps the primary.
+        selection.extend([e[0]]);
+        assert_eq!(selection.primary(), Some(e[1]));
+        selection.set([]);
+        assert!(selection.is_empty() && selection.primary().is_none());
+    }
+
+    #[test]
+    fn toggling_off_the_primary_picks_another() {
+        let e = entities(2);
+        let mut selection = Selection::default();
+        selection.toggle(e[0]);
+        selection.toggle(e[1]);
+        assert_eq!(selection.primary(), Some(e[0]));
+        selection.toggle(e[0]);
+        assert!(!selection.contains(e[0]));
+        assert_eq!(selection.primary(), Some(e[1]));
+        selection.toggle(e[1]);
+        assert_eq!(selection.primary(), None);
+    }
+
+    #[test]
+    fn despawned_entities_are_deselected() {
+        let mut app = TestApp::default();
+        app.add_plugin(SelectionPlugin {});
+        let kept = app.world.spawn_empty().id();
+        let despawned = app.world.spawn_empty().id();
+        app.world.resource_mut::<Selection>().set([despawned, kept]);
+        app.update();
+        assert!(app.world.get::<Selected>(kept).is_some());
+
+        app.world.despawn(despawned);
+        app.update();
+        let selection = app.world.resource::<Selection>();
+        assert_eq!(selection.iter().collect::<Vec<_>>(), [kept]);
+        assert_eq!(selection.primary(), Some(kept));
+    }
+}
@@ -0,0 +1,813 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::actions::*;
+use crate::hud::*;
+use crate::level::*;
+use crate::resources::*;
+
+use bevy::app::AppExit;
+use bevy::diagnostic::Diagnostics;
+use bevy::prelude::*;
+use bevy::window::{PresentMode, PrimaryWindow, WindowMode, WindowResized};
+use bevy_rapier3d::render::DebugRenderContext;
+use serde::{Deserialize, Serialize};
+
+pub const SETTINGS_PATH: &str = "config/settings.ron";
+// Window sizes the settings menu cycles through.
+pub const RESOLUTIONS: [(f32, f32); 4] = [
+    (1280.0, 720.0),
+    (1600.0, 900.0),
+    (1920.0, 1080.0),
+    (2560.0, 1440.0),
+];
+// Window height the UI is laid out for. Other heights scale it, within limits.
+const UI_REFERENCE_HEIGHT: f32 = 720.0;
+const MIN_UI_SCALE: f32 = 0.75;
+const MAX_UI_SCALE: f32 = 2.0;
+// Seconds the window has to stay the same size before a resize is saved.
+const RESIZE_SETTLE_TIME: f32 = 0.5;
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    MissingValue(String),
+    InvalidResolution(String),
+    UnknownFlags(Vec<String>),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(err) => write!(f, "failed to access settings file: {err}"),
+            SettingsError::Parse(err) => write!(f, "failed to parse settings file: {err}"),
+            SettingsError::Serialize(err) => write!(f, "failed to serialize settings: {err}"),
+            SettingsError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            SettingsError::InvalidResolution(value) => {
+                write!(f, "resolution should look like 1280x720, not {value:?}")
+            }
+            SettingsError::UnknownFlags(flags) => {
+                write!(f, "ignoring unknown flags: {}", flags.join(" "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(err: std::io::Error) -> Self {
+        SettingsError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SettingsError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SettingsError::Parse(err)
+    }
+}
+
+impl From<ron::Error> for SettingsError {
+    fn from(err: ron::Error) -> Self {
+        SettingsError::Serialize(err)
+    }
+}
+
+// Developer tools drawn over the game.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+#[serde(default)]
+pub struct DebugOverlays {
+    // Logs frame times to the console.
+    pub diagnostics: bool,
+    // Draws the outlines of colliders.
+    pub physics: bool,
+    pub inspector: bool,
+    // Lines along the world axes through the origin.
+    pub axes: bool,
+    // The paths targets take from spawn to goal.
+    pub route: bool,
+}
+
+impl Default for DebugOverlays {
+    fn default() -> Self {
+        Self {
+            diagnostics: true,
+            physics: true,
+            inspector: true,
+            axes: true,
+            route: true,
+        }
+    }
+}
+
+// Window and graphics options, from the settings file and the command line. Changing them at
+// runtime applies them and saves them to the file.
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize, Reflect)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct Settings {
+    // Size of the window in logical pixels, when it isn't fullscreen.
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    // Lets the level's lights cast shadows.
+    pub shadows: bool,
+    pub debug: DebugOverlays,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            width: RESOLUTIONS[0].0,
+            height: RESOLUTIONS[0].1,
+            fullscreen: false,
+            vsync: true,
+            shadows: true,
+            debug: DebugOverlays::default(),
+        }
+    }
+}
+
+// The settings file's contents, which changes made at runtime are saved into. Command line
+// overrides only apply to `Settings`, so a one-off flag is never saved.
+#[derive(Debug, Clone, Resource)]
+pub struct SavedSettings {
+    pub file: Settings,
+    // `Settings` as of the last save, to tell which options were changed since.
+    applied: Settings,
+}
+
+impl SavedSettings {
+    pub fn new(file: Settings) -> Self {
+        Self {
+            applied: file.clone(),
+            file,
+        }
+    }
+
+    // The settings file, if there is one. Runs before logging is set up, so problems are printed
+    // instead.
+    pub fn from_file() -> Self {
+        let file = if Path::new(SETTINGS_PATH).exists() {
+            Settings::load(SETTINGS_PATH).unwrap_or_else(|err| {
+                eprintln!("Using default settings: {err}");
+                Settings::default()
+            })
+        } else {
+            Settings::default()
+        };
+        Self::new(file)
+    }
+
+    // The file's settings overridden by the command line, to play with.
+    pub fn with_args(&mut self, args: impl IntoIterator<Item = String>) -> Settings {
+        let mut settings = self.file.clone();
+        if let Err(err) = settings.apply_args(args) {
+            eprintln!("{err}");
+        }
+        self.applied = settings.clone();
+        settings
+    }
+
+    // Takes the options changed in `settings` since the last update into the file.
+    pub fn update(&mut self, settings: &Settings) {
+        self.file.take_changes(&self.applied, settings);
+        self.applied = settings.clone();
+    }
+}
+
+impl FromWorld for SavedSettings {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world.get_resource::<Settings>().cloned();
+        Self::new(settings.unwrap_or_default())
+    }
+}
+
+// A line in the settings menu. Clicking it changes the option.
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Component)]
+pub enum SettingsOption {
+    #[default]
+    Resolution,
+    Fullscreen,
+    Vsync,
+    Shadows,
+    Diagnostics,
+    PhysicsDebug,
+    Inspector,
+    Axes,
+    Route,
+}
+
+impl SettingsOption {
+    pub const ALL: [SettingsOption; 9] = [
+        SettingsOption::Resolution,
+        SettingsOption::Fullscreen,
+        SettingsOption::Vsync,
+        SettingsOption::Shadows,
+        SettingsOption::Diagnostics,
+        SettingsOption::PhysicsDebug,
+        SettingsOption::Inspector,
+        SettingsOption::Axes,
+        SettingsOption::Route,
+    ];
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "On"
+    } else {
+        "Off"
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<(f32, f32), SettingsError> {
+    let invalid = || SettingsError::InvalidResolution(value.to_string());
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width: f32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: f32 = height.trim().parse().map_err(|_| invalid())?;
+    check_resolution(width, height).map_err(|_| invalid())?;
+    Ok((width, height))
+}
+
+fn check_resolution(width: f32, height: f32) -> Result<(), SettingsError> {
+    if !(width.is_finite() && height.is_finite() && width >= 1.0 && height >= 1.0) {
+        return Err(SettingsError::InvalidResolution(format!(
+            "{width}x{height}"
+        )));
+    }
+    Ok(())
+}
+
+// Flags read by other parts of the game, which settings leave alone.
+const OTHER_FLAGS: [&str; 1] = ["level"];
+
+// Copies `after` into `value` if it differs from `before`.
+fn take_change<T: PartialEq + Copy>(value: &mut T, before: T, after: T) {
+    if before != after {
+        *value = after;
+    }
+}
+
+impl Settings {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let contents = std::fs::read_to_string(path)?;
+        let settings: Settings = ron::from_str(&contents)?;
+        check_resolution(settings.width, settings.height)?;
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    // Copies the options which differ between `before` and `after` into these settings.
+    fn take_changes(&mut self, before: &Settings, after: &Settings) {
+        take_change(&mut self.width, before.width, after.width);
+        take_change(&mut self.height, before.height, after.height);
+        take_change(&mut self.fullscreen, before.fullscreen, after.fullscreen);
+        take_change(&mut self.vsync, before.vsync, after.vsync);
+        take_change(&mut self.shadows, before.shadows, after.shadows);
+        let (debug, before, after) = (&mut self.debug, &before.debug, &after.debug);
+        take_change(
+            &mut debug.diagnostics,
+            before.diagnostics,
+            after.diagnostics,
+        );
+        take_change(&mut debug.physics, before.physics, after.physics);
+        take_change(&mut debug.inspector, before.inspector, after.inspector);
+        take_change(&mut debug.axes, before.axes, after.axes);
+        take_change(&mut debug.route, before.route, after.route);
+    }
+
+    // Overrides settings from command line flags like `--resolution 1920x1080`, `--fullscreen`
+    // or `--no-inspector`. Flags for other things, like `--level`, are left alone. Unknown flags
+    // are reported once the rest have been applied.
+    pub fn apply_args(
+        &mut self,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(), SettingsError> {
+        let mut unknown = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Values of other flags, like the level's path, aren't flags themselves.
+            let Some(flag) = arg.strip_prefix("--") else {
+                continue;
+            };
+            let (flag, enabled) = match flag.strip_prefix("no-") {
+                Some(flag) => (flag, false),
+                None => (flag, true),
+            };
+            match flag {
+                "fullscreen" => self.fullscreen = enabled,
+                "windowed" => self.fullscreen = !enabled,
+                "vsync" => self.vsync = enabled,
+                "shadows" => self.shadows = enabled,
+                "diagnostics" => self.debug.diagnostics = enabled,
+                "physics-debug" => self.debug.physics = enabled,
+                "inspector" => self.debug.inspector = enabled,
+                "axes" => self.debug.axes = enabled,
+                "route" => self.debug.route = enabled,
+                "debug" => {
+                    self.debug = DebugOverlays {
+                        diagnostics: enabled,
+                        physics: enabled,
+                        inspector: enabled,
+                        axes: enabled,
+                        route: enabled,
+                    }
+                }
+                "resolution" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| SettingsError::MissingValue(arg.clone()))?;
+                    (self.width, self.height) = parse_resolution(&value)?;
+                }
+                _ => {
+                    if let Some(value) = flag.strip_prefix("resolution=") {
+                        (self.width, self.height) = parse_resolution(value)?;
+                    } else {
+                        let name = flag.split_once('=').map_or(flag, |(name, _)| name);
+                        if !OTHER_FLAGS.contains(&name) {
+                            unknown.push(arg.clone());
+                        }
+                    }
+                }
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(SettingsError::UnknownFlags(unknown));
+        }
+        Ok(())
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+
+    pub fn apply_to_window(&self, window: &mut Window) {
+        window.mode = self.window_mode();
+        window.present_mode = self.present_mode();
+        if !self.fullscreen {
+            window.resolution.set(self.width, self.height);
+        }
+    }
+
+    pub fn toggle(&mut self, option: SettingsOption) {
+        match option {
+            SettingsOption::Resolution => {
+                // The next size up, wrapping around to the smallest.
+                (self.width, self.height) = RESOLUTIONS
+                    .into_iter()
+                    .find(|&(width, height)| (width, height) > (self.width, self.height))
+                    .unwrap_or(RESOLUTIONS[0]);
+            }
+            SettingsOption::Fullscreen => self.fullscreen = !self.fullscreen,
+            SettingsOption::Vsync => self.vsync = !self.vsync,
+            SettingsOption::Shadows => self.shadows = !self.shadows,
+            SettingsOption::Diagnostics => self.debug.diagnostics = !self.debug.diagnostics,
+            SettingsOption::PhysicsDebug => self.debug.physics = !self.debug.physics,
+            SettingsOption::Inspector => self.debug.inspector = !self.debug.inspector,
+            SettingsOption::Axes => self.debug.axes = !self.debug.axes,
+            SettingsOption::Route => self.debug.route = !self.debug.route,
+        }
+    }
+
+    pub fn describe(&self, option: SettingsOption) -> String {
+        match option {
+            SettingsOption::Resolution => format!("Resolution: {}x{}", self.width, self.height),
+            SettingsOption::Fullscreen => format!("Fullscreen: {}", on_off(self.fullscreen)),
+            SettingsOption::Vsync => format!("VSync: {}", on_off(self.vsync)),
+            SettingsOption::Shadows => format!("Shadows: {}", on_off(self.shadows)),
+            SettingsOption::Diagnostics => {
+                format!("Log diagnostics: {}", on_off(self.debug.diagnostics))
+            }
+            SettingsOption::PhysicsDebug => {
+                format!("Physics debug: {}", on_off(self.debug.physics))
+            }
+            SettingsOption::Inspector => format!("Inspector: {}", on_off(self.debug.inspector)),
+            SettingsOption::Axes => format!("Axes: {}", on_off(self.debug.axes)),
+            SettingsOption::Route => format!("Route: {}", on_off(self.debug.route)),
+        }
+    }
+}
+
+// Run conditions for the debug overlays which are toggled by skipping their systems.
+pub fn show_inspector(settings: Res<Settings>) -> bool {
+    settings.debug.inspector
+}
+
+pub fn show_axes(settings: Res<Settings>) -> bool {
+    settings.debug.axes
+}
+
+pub fn show_route(settings: Res<Settings>) -> bool {
+    settings.debug.route
+}
+
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct SettingsMenu;
+
+// A window size recorded while the player drags the window, waiting to be saved.
+#[derive(Debug, Default, Resource)]
+struct PendingResize {
+    settle: Option<Timer>,
+}
+
+pub struct SettingsPlugin {}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Settings>()
+            .register_type::<SettingsOption>()
+            .register_type::<SettingsMenu>()
+            .init_resource::<Settings>()
+            .init_resource::<SavedSettings>()
+            .init_resource::<PendingResize>()
+            .add_startup_system(spawn_settings_menu)
+            .add_systems((
+                toggle_settings_menu,
+                handle_settings_buttons,
+                record_window_size.before(apply_settings),
+                apply_settings,
+                update_shadows,
+                update_settings_labels,
+                scale_ui,
+                save_settings,
+            ));
+    }
+}
+
+fn spawn_settings_menu(mut commands: Commands, assets: Res<GameAssets>) {
+    // Full screen container which centers the panel.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::all(Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(SettingsMenu)
+        .insert(Name::new("SettingsMenu"))
+        .with_children(|container| {
+            container
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(12.0)),
+                        gap: Size::height(Val::Px(6.0)),
+                        min_size: Size::width(Val::Px(260.0)),
+                        ..default()
+                    },
+                    background_color: PANEL_COLOR.into(),
+                    ..default()
+                })
+                .insert(Interaction::default())
+                .with_children(|panel| {
+                    panel.spawn(TextBundle::from_section(
+                        "Settings (F10 to close)",
+                        text_style(&assets, 20.0),
+                    ));
+                    for option in SettingsOption::ALL {
+                        panel
+                            .spawn(ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::all(Val::Px(4.0)),
+                                    ..default()
+                                },
+                                background_color: BUTTON_COLOR.into(),
+                                ..default()
+                            })
+                            .insert(option)
+                            .with_children(|button| {
+                                button
+                                    .spawn(TextBundle::from_section("", text_style(&assets, 16.0)));
+                            });
+                    }
+                });
+        });
+}
+
+fn toggle_settings_menu(
+    actions: Res<ActionState>,
+    mut menu: Query<&mut Visibility, With<SettingsMenu>>,
+) {
+    if !actions.just_pressed(Action::ToggleSettings) {
+        return;
+    }
+    for mut visibility in &mut menu {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn handle_settings_buttons(
+    mut buttons: Query<(&Interaction, &SettingsOption, &mut BackgroundColor), Changed<Interaction>>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, option, mut color) in &mut buttons {
+        *color = match interaction {
+            Interaction::Clicked => BUTTON_PRESSED_COLOR,
+            Interaction::Hovered => BUTTON_HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+        if *interaction == Interaction::Clicked {
+            settings.toggle(*option);
+        }
+    }
+}
+
+fn update_settings_labels(
+    settings: Res<Settings>,
+    pending_resize: Res<PendingResize>,
+    buttons: Query<(&SettingsOption, &Children)>,
+    mut labels: Query<&mut Text>,
+) {
+    if !settings.is_changed() && !pending_resize.is_changed() {
+        return;
+    }
+    for (option, children) in &buttons {
+        for child in children {
+            if let Ok(mut text) = labels.get_mut(*child) {
+                text.sections[0].value = settings.describe(*option);
+            }
+        }
+    }
+}
+
+// Remember the size the player drags the window to, so that it opens that size next time. The
+// window already has this size, so it isn't applied back to it, and it's saved once the drag
+// settles rather than on every frame of it.
+fn record_window_size(
+    mut resized: EventReader<WindowResized>,
+    primary_query: Query<Entity, With<PrimaryWindow>>,
+    mut settings: ResMut<Settings>,
+    mut pending_resize: ResMut<PendingResize>,
+) {
+    let Some(event) = resized
+        .iter()
+        .filter(|event| primary_query.contains(event.window))
+        .last()
+    else {
+        return;
+    };
+    let unchanged =
+        (event.width - settings.width).abs() < 0.5 && (event.height - settings.height).abs() < 0.5;
+    if settings.fullscreen || unchanged {
+        return;
+    }
+    let settings = settings.bypass_change_detection();
+    settings.width = event.width;
+    settings.height = event.height;
+    pending_resize.settle = Some(Timer::from_seconds(RESIZE_SETTLE_TIME, TimerMode::Once));
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut primary_query: Query<&mut Window, With<PrimaryWindow>>,
+    debug_render: Option<ResMut<DebugRenderContext>>,
+    diagnostics: Option<ResMut<Diagnostics>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    if let Ok(mut window) = primary_query.get_single_mut() {
+        settings.apply_to_window(&mut window);
+    }
+    if let Some(mut debug_render) = debug_render {
+        debug_render.enabled = settings.debug.physics;
+    }
+    // Disabled diagnostics are neither measured nor logged.
+    if let Some(mut diagnostics) = diagnostics {
+        let ids: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.id).collect();
+        for id in ids {
+            if let Some(diagnostic) = diagnostics.get_mut(id) {
+                diagnostic.is_enabled = settings.debug.diagnostics;
+            }
+        }
+    }
+}
+
+// Level lights are respawned whenever the level changes, so they are updated as they appear too.
+fn update_shadows(settings: Res<Settings>, mut lights: Query<(&mut PointLight, Ref<LevelLight>)>) {
+    for (mut light, level_light) in &mut lights {
+        if settings.is_changed() || level_light.is_added() {
+            light.shadows_enabled = level_light.shadows && settings.shadows;
+        }
+    }
+}
+
+// Keep the UI the same size relative to the window as it is at the reference height.
+fn scale_ui(
+    primary_query: Query<&Window, (With<PrimaryWindow>, Changed<Window>)>,
+    mut ui_scale: ResMut<UiScale>,
+) {
+    let Ok(window) = primary_query.get_single() else {
+        return;
+    };
+    let scale = (window.height() / UI_REFERENCE_HEIGHT).clamp(MIN_UI_SCALE, MAX_UI_SCALE) as f64;
+    if ui_scale.scale != scale {
+        ui_scale.scale = scale;
+    }
+}
+
+// Persist settings whenever they are changed at runtime. Resizes wait until the window has
+// stopped changing size, or until the game exits.
+fn save_settings(
+    settings: Res<Settings>,
+    mut saved: ResMut<SavedSettings>,
+    mut pending_resize: ResMut<PendingResize>,
+    time: Res<Time>,
+    exit: EventReader<AppExit>,
+) {
+    let resize_settled = match &mut pending_resize.bypass_change_detection().settle {
+        Some(settle) => settle.tick(time.raw_delta()).finished() || !exit.is_empty(),
+        None => false,
+    };
+    if !resize_settled && (!settings.is_changed() || settings.is_added()) {
+        return;
+    }
+    pending_resize.bypass_change_detection().settle = None;
+    saved.update(&settings);
+    if let Err(err) = saved.file.save(SETTINGS_PATH) {
+        error!("{err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn args(args: &str) -> impl Iterator<Item = String> + '_ {
+        args.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn flags_override_settings() {
+        let mut settings = Settings::default();
+        settings
+            .apply_args(args(
+                "--resolution 1920x1080 --fullscreen --no-vsync --no-debug --physics-debug \
+                 --level axes",
+            ))
+            .unwrap();
+        assert_eq!((settings.width, settings.height), (1920.0, 1080.0));
+        assert!(settings.fullscreen && !settings.vsync && settings.shadows);
+        assert_eq!(
+            settings.debug,
+            DebugOverlays {
+                diagnostics: false,
+                physics: true,
+                inspector: false,
+                axes: false,
+                route: false,
+            }
+        );
+
+        settings.apply_args(args("--resolution=800x600")).unwrap();
+        assert_eq!((settings.width, settings.height), (800.0, 600.0));
+        assert!(settings.apply_args(args("--resolution big")).is_err());
+        assert!(settings.apply_args(args("--resolution")).is_err());
+    }
+
+    #[test]
+    fn unknown_flags_are_reported_after_the_rest_apply() {
+        let mut settings = Settings {
+            vsync: false,
+            ..default()
+        };
+        let result = settings.apply_args(args("--fulscreen --vsync --level=a --level b --no-axs"));
+        match result {
+            Err(SettingsError::UnknownFlags(flags)) => {
+                assert_eq!(flags, ["--fulscreen", "--no-axs"]);
+            }
+            other => panic!("expected unknown flags, got {other:?}"),
+        }
+        assert!(settings.vsync && !settings.fullscreen);
+    }
+
+    #[test]
+    fn files_with_impossible_sizes_are_rejected() {
+        let dir =
+            std::env::temp_dir().join(format!("bevy-tutorial-settings-{}", std::process::id()));
+        let path = dir.join("settings.ron");
+        let mut results = Vec::new();
+        for (width, height) in [(0.0, 720.0), (1280.0, -5.0), (1280.0, 720.0)] {
+            Settings {
+                width,
+                height,
+                ..default()
+            }
+            .save(&path)
+            .unwrap();
+            results.push(Settings::load(&path).is_ok());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(results, [false, false, true]);
+    }
+
+    #[test]
+    fn command_line_overrides_are_not_saved() {
+        let mut saved = SavedSettings::new(Settings::default());
+        let mut settings = saved.with_args(args("--no-inspector --resolution 1920x1080"));
+        assert!(!settings.debug.inspector);
+
+        settings.toggle(SettingsOption::Shadows);
+        saved.update(&settings);
+        assert!(!saved.file.shadows);
+        assert!(saved.file.debug.inspector);
+        assert_eq!(saved.file.width, Settings::default().width);
+
+        // Changing an overridden option at runtime saves it after all.
+        (settings.width, settings.height) = (1000.0, 800.0);
+        saved.update(&settings);
+        assert_eq!((saved.file.width, saved.file.height), (1000.0, 800.0));
+    }
+
+    #[test]
+    fn resolution_cycles_through_presets() {
+        let mut settings = Settings {
+            width: 1366.0,
+            height: 768.0,
+            ..default()
+        };
+        settings.toggle(SettingsOption::Resolution);
+        assert_eq!((settings.width, settings.height), RESOLUTIONS[1]);
+        (settings.width, settings.height) = RESOLUTIONS[RESOLUTIONS.len() - 1];
+        settings.toggle(SettingsOption::Resolution);
+        assert_eq!((settings.width, settings.height), RESOLUTIONS[0]);
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let settings: Settings = ron::from_str("(fullscreen: true, debug: (axes: false))").unwrap();
+        assert!(settings.fullscreen && !settings.debug.axes && settings.debug.inspector);
+        assert_eq!(settings.width, Settings::default().width);
+        let saved = ron::to_string(&settings).unwrap();
+        assert_eq!(ron::from_str::<Settings>(&saved).unwrap(), settings);
+    }
+
+    #[test]
+    fn resizes_are_recorded_without_resizing_the_window() {
+        let mut app = TestApp::default();
+        app.add_event::<WindowResized>()
+            .init_resource::<Settings>()
+            .init_resource::<PendingResize>()
+            .add_systems((record_window_size.before(apply_settings), apply_settings));
+        app.update();
+        // The window has already moved on by the time the event is read, mid drag.
+        let window = app
+            .world
+            .spawn((
+                Window {
+                    resolution: (1310.0, 710.0).into(),
+                    ..default()
+                },
+                PrimaryWindow,
+            ))
+            .id();
+        app.world.send_event(WindowResized {
+            window,
+            width: 1300.0,
+            height: 700.0,
+        });
+        app.update();
+
+        let settings = app.world.resource::<Settings>();
+        assert_eq!((settings.width, settings.height), (1300.0, 700.0));
+        assert!(app.world.resource::<PendingResize>().settle.is_some());
+        let window = app.world.get::<Window>(window).unwrap();
+        assert_eq!((window.width(), window.height()), (1310.0, 710.0));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::components::*;
+use crate::health::*;
+use crate::target::*;
+
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+// How hard targets push apart when their hitboxes overlap, relative to their speed.
+pub const SEPARATION_STRENGTH: f32 = 1.5;
+
+// How quickly targets turn towards where they want to go. Higher is snappier.
+pub const TURN_RESPONSIVENESS: f32 = 6.0;
+
+// Targets never move faster than this multiple of their `Velocity`, however hard they're pushed.
+pub const MAX_SPEED_FACTOR: f32 = 1.25;
+
+// Steers the target around its neighbors on the way to where its `Velocity` points. Targets
+// with `Steering` move by its velocity instead of their `Velocity`.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Steering {
+    pub velocity: Vec3,
+}
+
+impl Steering {
+    pub fn new(velocity: Vec3) -> Self {
+        Self { velocity }
+    }
+}
+
+// Buckets positions on the xz plane so that nearby ones can be found without comparing every pair.
+#[derive(Debug)]
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(Vec3, T)>>,
+}
+
+impl<T: Copy> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> IVec2 {
+        (position.xz() / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn insert(&mut self, position: Vec3, item: T) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push((position, item));
+    }
+
+    // Items in the cells around `position`, which include all of those within `cell_size`.
+    pub fn nearby(&self, position: Vec3) -> impl Iterator<Item = &(Vec3, T)> + '_ {
+        let cell = self.cell(position);
+        (-1..=1)
+            .flat_map(move |x| (-1..=1).map(move |z| cell + IVec2::new(x, z)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+    }
+}
+
+pub struct SteeringPlugin {}
+
+impl Plugin for SteeringPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Steering>().add_system(
+            steer_targets
+                .after(TargetSet::Navigate)
+                .before(TargetSet::Move),
+        );
+    }
+}
+
+fn steer_targets(
+    mut targets: Query<(Entity, &Transform, &Target, &Velocity, &mut Steering), Without<Dead>>,
+    time: Res<Time>,
+) {
+    let widest = targets
+        .iter()
+        .map(|(_, _, target, ..)| target.hitbox)
+        .fold(0.0, f32::max);
+    if widest <= 0.0 {
+        return;
+    }
+    let mut neighbors = SpatialHash::new(widest * 2.0);
+    for (entity, transform, target, ..) in &targets {
+        neighbors.insert(transform.translation, (entity, target.hitbox));
+    }
+
+    let turn = 1.0 - (-TURN_RESPONSIVENESS * time.delta_seconds()).exp();
+    for (entity, transform, target, velocity, mut steering) in &mut targets {
+        let position = transform.translation;
+        let speed = velocity.val.length();
+        // Targets stacked exactly on top of each other split to either side of their heading.
+        let aside = velocity.val.cross(Vec3::Y).normalize_or_zero();
+
+        let mut push = Vec3::ZERO;
+        for (other_position, (other, other_hitbox)) in neighbors.nearby(position) {
+            if *other == entity {
+                continue;
+            }
+            let reach = target.hitbox + other_hitbox;
+            let offset = position - *other_position;
+            let distance = offset.length();
+            if distance >= reach {
+                continue;
+            }
+            let away = Vec3::new(offset.x, 0.0, offset.z)
+                .try_normalize()
+                .unwrap_or(if entity < *other { aside } else { -aside });
+            push += away * (1.0 - distance / reach);
+        }
+
+        let desired = velocity.val + push * SEPARATION_STRENGTH * speed;
+        steering.velocity = steering
+            .velocity
+            .lerp(desired, turn)
+            .clamp_length_max(speed * MAX_SPEED_FACTOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::*;
+    use crate::test_utils::*;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::default();
+        app.add_plugin(HealthPlugin {})
+            .add_plugin(TargetPlugin {})
+            .add_plugin(SteeringPlugin {})
+            .insert_resource(PlayerStats::default())
+            // Head straight along +z.
+            .insert_resource(Route {
+                goal: Vec3::new(0.0, 0.0, 100.0),
+                ground_path: Vec::new(),
+                ..default()
+            });
+        app
+    }
+
+    fn spawn(app: &mut App, x: f32) -> Entity {
+        app.world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(x, 0.0, 0.0)),
+                TargetBundle::new(10.0, Vec3::Z, 0.2),
+                Steering::new(Vec3::Z),
+            ))
+            .id()
+    }
+
+    fn position(app: &App, entity: Entity) -> Vec3 {
+        app.world.get::<Transform>(entity).unwrap().translation
+    }
+
+    #[test]
+    fn stacked_targets_separate() {
+        let mut app = app();
+        let targets = [spawn(&mut app, 0.0), spawn(&mut app, 0.0)];
+        app.run(40);
+        let (a, b) = (position(&app, targets[0]), position(&app, targets[1]));
+        assert!(a.distance(b) >= 0.3, "still overlapping: {a} {b}");
+        // Both still made progress along their path.
+        assert!(a.z > 1.0 && b.z > 1.0);
+    }
+
+    #[test]
+    fn pushing_never_exceeds_max_speed() {
+        let mut app = app();
+        for _ in 0..10 {
+            spawn(&mut app, 0.0);
+        }
+        app.run(5);
+        for steering in app.world.query::<&Steering>().iter(&app.world) {
+            assert!(steering.velocity.length() <= MAX_SPEED_FACTOR + 1e-4);
+        }
+    }
+}
@@ -1,6 +1,16 @@
+use crate::collision::*;
 use crate::components::*;
+use crate::flow_field::*;
+use crate::health::*;
+use crate::nav::*;
+use crate::picking::*;
+use crate::resources::*;
+use crate::steering::*;
+use crate::terrain::*;
 
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
+use bevy_rapier3d::prelude::{Collider, RigidBody};
 
 #[derive(Debug, Component, Default, Reflect)]
 #[reflect(Component)]
@@ -8,43 +18,509 @@ pub struct Target {
     pub hitbox: f32,
 }
 
+// Distance at which a target counts as having reached a waypoint.
+pub const WAYPOINT_RADIUS: f32 = 0.05;
+
+// Time the death effect plays for before a target is despawned.
+pub const DEATH_DURATION: f32 = 0.5;
+
+// Gold awarded to the player for killing a target.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Bounty {
+    pub gold: u32,
+}
+
+// A dead target playing its death effect. Despawned once the timer finishes.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Dying {
+    pub timer: Timer,
+    // Scale when the target died, which the effect shrinks from.
+    pub start_scale: Vec3,
+}
+
+// Targets which fly over the map rather than walking on the ground.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Flying {
+    // Height above the ground.
+    pub altitude: f32,
+}
+
+impl Flying {
+    pub fn layer(flying: Option<&Flying>) -> PathLayer {
+        match flying {
+            Some(_) => PathLayer::Air,
+            None => PathLayer::Ground,
+        }
+    }
+}
+
+// Progress along the route's path for the target's layer. Targets steer towards `waypoint` at
+// the speed of their velocity. On the ground, they follow the `RouteFlowFields` there instead,
+// around any towers in the way.
+#[derive(Debug, Clone, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct PathFollower {
+    // Index into the path being followed.
+    pub waypoint: usize,
+}
+
+impl PathFollower {
+    pub fn next_waypoint(&self, route: &Route, layer: PathLayer) -> Option<Vec3> {
+        route.waypoint(route.path(layer), self.waypoint)
+    }
+
+    // Distance left to the goal, along the flow fields for ground targets if there are some.
+    pub fn remaining_distance(
+        &self,
+        route: &Route,
+        layer: PathLayer,
+        position: Vec3,
+        flow: Option<(&RouteFlowFields, &NavGrid)>,
+    ) -> f32 {
+        if let (PathLayer::Ground, Some((fields, grid))) = (layer, flow) {
+            if let Some(distance) = fields.distance(grid, position, self.waypoint) {
+                return distance;
+            }
+        }
+        route.remaining_distance(route.path(layer), self.waypoint, position)
+    }
+}
+
 #[derive(Debug, Bundle, Default)]
 pub struct TargetBundle {
     pub velocity: Velocity,
     pub health: Health,
     pub target: Target,
+    pub path_follower: PathFollower,
 }
 
 impl TargetBundle {
     pub fn new(health: f32, velocity: Vec3, hitbox: f32) -> Self {
         Self {
             velocity: Velocity { val: velocity },
-            health: Health { val: health },
+            health: Health::new(health),
             target: Target { hitbox },
+            path_follower: PathFollower::default(),
         }
     }
 }
 
+pub fn spawn_target(
+    commands: &mut Commands,
+    scene: Handle<Scene>,
+    location: Transform,
+    target: TargetBundle,
+    collider: Collider,
+) -> Entity {
+    commands
+        .spawn(SceneBundle {
+            scene,
+            transform: location,
+            ..default()
+        })
+        .insert(target)
+        // Targets are small and often in front of towers, so they win when both are under the
+        // cursor.
+        .insert(Selectable { priority: 1 })
+        .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
+        .with_children(|child_cmd| {
+            child_cmd
+                .spawn(collider)
+                .insert(member_of(TARGETS | PICKABLE))
+                .insert(Transform::from_xyz(0.0, 0.0, 0.0))
+                .insert(Name::new("Hitbox"));
+        })
+        .insert(Name::new("Target"))
+        .id()
+}
+
+// Each frame, targets first work out where to head, then move. Systems which adjust how they
+// move, like steering around each other, run in between.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub enum TargetSet {
+    Navigate,
+    Move,
+}
+
 pub struct TargetPlugin {}
 
 impl Plugin for TargetPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<Target>().add_system(update_targets);
+        app.register_type::<Target>()
+            .register_type::<Flying>()
+            .register_type::<PathFollower>()
+            .register_type::<Bounty>()
+            .register_type::<Dying>()
+            .configure_sets((TargetSet::Navigate, TargetSet::Move).chain())
+            .add_system(update_targets.in_set(TargetSet::Navigate))
+            .add_system(move_targets.in_set(TargetSet::Move))
+            .add_system(reach_goal.after(TargetSet::Move))
+            .add_system(start_dying)
+            .add_system(award_bounties)
+            .add_system(play_death_effect);
     }
 }
 
-fn update_targets(
+// Targets which make it to the goal cost the player a life. Height is ignored so that flying
+// targets reach the goal by passing over it.
+fn reach_goal(
+    mut commands: Commands,
+    targets: Query<(Entity, &Transform), (With<Target>, Without<Dead>)>,
+    route: Res<Route>,
+    mut stats: ResMut<PlayerStats>,
+) {
+    for (entity, transform) in &targets {
+        if (transform.translation - route.goal).xz().length() > route.goal_radius {
+            continue;
+        }
+        stats.lives = stats.lives.saturating_sub(1);
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn start_dying(
+    mut commands: Commands,
+    mut died: EventReader<Died>,
+    targets: Query<&Transform, With<Target>>,
+) {
+    for event in died.iter() {
+        let Ok(transform) = targets.get(event.entity) else {
+            continue;
+        };
+        commands.entity(event.entity).insert(Dying {
+            timer: Timer::from_seconds(DEATH_DURATION, TimerMode::Once),
+            start_scale: transform.scale,
+        });
+    }
+}
+
+fn award_bounties(
+    mut died: EventReader<Died>,
+    bounties: Query<&Bounty>,
+    mut stats: ResMut<PlayerStats>,
+) {
+    for event in died.iter() {
+        if let Ok(bounty) = bounties.get(event.entity) {
+            stats.gold += bounty.gold;
+        }
+    }
+}
+
+// Shrink dying targets away, then despawn them.
+fn play_death_effect(
     mut commands: Commands,
-    mut targets: Query<(Entity, &mut Transform, &Health, &Velocity), With<Target>>,
+    mut dying: Query<(Entity, &mut Dying, &mut Transform)>,
     time: Res<Time>,
 ) {
-    // Move live targets.
-    for (entity, mut transform, health, velocity) in targets.iter_mut() {
-        if health.val <= 0.0 {
+    for (entity, mut dying, mut transform) in &mut dying {
+        dying.timer.tick(time.delta());
+        if dying.timer.finished() {
             commands.entity(entity).despawn_recursive();
-            return; // Exits this scope, not the entire iteration.
+            continue;
+        }
+        transform.scale = dying.start_scale * dying.timer.percent_left();
+    }
+}
+
+// Points each target's `Velocity` along its path, passing the waypoints it has reached.
+fn update_targets(
+    mut targets: Query<
+        (
+            &Transform,
+            &mut Velocity,
+            &mut PathFollower,
+            Option<&Flying>,
+        ),
+        (With<Target>, Without<Dead>),
+    >,
+    route: Res<Route>,
+    flow_fields: Option<Res<RouteFlowFields>>,
+    grid: Option<Res<NavGrid>>,
+    time: Res<Time>,
+) {
+    for (transform, mut velocity, mut follower, flying) in &mut targets {
+        let layer = Flying::layer(flying);
+        let speed = velocity.val.length();
+        let step = (speed * time.delta_seconds()).max(WAYPOINT_RADIUS);
+
+        if let (PathLayer::Ground, Some(fields), Some(grid)) = (layer, &flow_fields, &grid) {
+            // Pass the waypoints reached this frame, but never the goal.
+            let goal_index = route.ground_path.len();
+            while follower.waypoint < goal_index
+                && (fields.checkpoint(follower.waypoint) - transform.translation)
+                    .xz()
+                    .length()
+                    <= step
+            {
+                follower.waypoint += 1;
+            }
+            // Head straight for the waypoint once in its cell.
+            let direction = fields
+                .field(follower.waypoint)
+                .direction(grid, transform.translation)
+                .or_else(|| {
+                    let offset = fields.checkpoint(follower.waypoint) - transform.translation;
+                    Vec3::new(offset.x, 0.0, offset.z).try_normalize()
+                });
+            if let Some(direction) = direction {
+                velocity.val = direction * speed;
+            }
+            continue;
         }
 
-        transform.translation += velocity.val * time.delta_seconds();
+        let altitude = flying.map_or(0.0, |flying| flying.altitude);
+        let goal_index = route.path(layer).len();
+
+        // Skip past waypoints which are reached this frame, but never past the goal.
+        while let Some(waypoint) = follower.next_waypoint(&route, layer) {
+            let offset = waypoint + Vec3::Y * altitude - transform.translation;
+            if follower.waypoint < goal_index && offset.length() <= step {
+                follower.waypoint += 1;
+                continue;
+            }
+            if let Some(direction) = offset.try_normalize() {
+                velocity.val = direction * speed;
+            }
+            break;
+        }
+    }
+}
+
+// Moves targets by the velocity worked out for them this frame.
+fn move_targets(
+    mut targets: Query<
+        (
+            &mut Transform,
+            &Velocity,
+            Option<&Flying>,
+            Option<&Steering>,
+        ),
+        (With<Target>, Without<Dead>),
+    >,
+    terrain: Option<Res<Terrain>>,
+    time: Res<Time>,
+) {
+    for (mut transform, velocity, flying, steering) in &mut targets {
+        let moved =
+            steering.map_or(velocity.val, |steering| steering.velocity) * time.delta_seconds();
+        let to = transform.translation + moved;
+        transform.translation = match (flying, &terrain) {
+            // Ground targets keep the same clearance above the terrain as they move.
+            (None, Some(terrain)) => terrain.snap(Vec3::new(
+                to.x,
+                terrain.clearance(transform.translation),
+                to.z,
+            )),
+            _ => to,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    use std::time::Duration;
+
+    const FRAME: Duration = Duration::from_millis(100);
+
+    fn app() -> TestApp {
+        let mut app = TestApp::default();
+        app.frame = FRAME;
+        app.add_plugin(HealthPlugin {})
+            .add_plugin(TargetPlugin {})
+            .insert_resource(PlayerStats::default())
+            // Head straight along +z.
+            .insert_resource(Route {
+                goal: Vec3::new(0.0, 0.0, 100.0),
+                ground_path: Vec::new(),
+                ..default()
+            });
+        // The first update only initializes time.
+        app.update();
+        app
+    }
+
+    fn spawn_target(app: &mut App, x: f32, health: f32) -> Entity {
+        app.world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(x, 0.0, 0.0)),
+                TargetBundle::new(health, Vec3::Z, 0.2),
+            ))
+            .id()
+    }
+
+    fn position_z(app: &App, entity: Entity) -> f32 {
+        app.world.get::<Transform>(entity).unwrap().translation.z
+    }
+
+    fn damage(app: &mut App, target: Entity, amount: f32, source: Option<Entity>) {
+        app.world.send_event(DamageEvent {
+            target,
+            amount,
+            source,
+        });
+    }
+
+    #[test]
+    fn live_targets_move_when_one_dies() {
+        let mut app = app();
+        let targets: Vec<Entity> = (0..4)
+            .map(|i| spawn_target(&mut app, i as f32, 5.0))
+            .collect();
+        let victim = targets[1];
+
+        // Both on the frame the death is detected and on the frame the target starts dying,
+        // every other target keeps moving.
+        damage(&mut app, victim, 10.0, None);
+        for _ in 0..2 {
+            let before: Vec<f32> = targets
+                .iter()
+                .map(|entity| position_z(&app, *entity))
+                .collect();
+            app.update();
+            for (entity, z) in targets.iter().zip(before) {
+                if *entity != victim {
+                    assert!(
+                        position_z(&app, *entity) > z,
+                        "live target {entity:?} didn't move"
+                    );
+                }
+            }
+        }
+        assert!(app.world.get::<Dead>(victim).is_some());
+        assert!(app.world.get::<Dying>(victim).is_some());
+
+        // Dying targets stay where they died.
+        let z = position_z(&app, victim);
+        app.update();
+        assert_eq!(position_z(&app, victim), z);
+    }
+
+    #[test]
+    fn targets_move_the_way_they_head_on_the_same_frame() {
+        let mut app = app();
+        // Facing away from the goal, which is straight along +z.
+        let target = app
+            .world
+            .spawn((
+                TransformBundle::default(),
+                TargetBundle::new(5.0, Vec3::X, 0.2),
+            ))
+            .id();
+        app.update();
+        let position = app.world.get::<Transform>(target).unwrap().translation;
+        assert_eq!(position.x, 0.0);
+        assert!(position.z > 0.0);
+    }
+
+    #[test]
+    fn died_reports_killer() {
+        let mut app = app();
+        let target = spawn_target(&mut app, 0.0, 5.0);
+        let tower = app.world.spawn_empty().id();
+
+        damage(&mut app, target, 2.0, None);
+        damage(&mut app, target, 3.0, Some(tower));
+        app.update();
+
+        let died: Vec<Died> = sent_events(&app);
+        assert_eq!(died.len(), 1);
+        assert_eq!(died[0].entity, target);
+        assert_eq!(died[0].killer, Some(tower));
+    }
+
+    #[test]
+    fn bounty_paid_once_and_target_despawned_after_effect() {
+        let mut app = app();
+        let target = spawn_target(&mut app, 0.0, 5.0);
+        app.world.entity_mut(target).insert(Bounty { gold: 7 });
+        let gold = app.world.resource::<PlayerStats>().gold;
+
+        damage(&mut app, target, 5.0, None);
+        app.update();
+        // Hitting the corpse again doesn't kill it twice.
+        damage(&mut app, target, 5.0, None);
+        app.update();
+        assert_eq!(app.world.resource::<PlayerStats>().gold, gold + 7);
+
+        let frames = (DEATH_DURATION / FRAME.as_secs_f32()).ceil() as usize;
+        for _ in 0..frames {
+            assert!(app.world.get_entity(target).is_some());
+            app.update();
+        }
+        assert!(app.world.get_entity(target).is_none());
+        assert_eq!(app.world.resource::<PlayerStats>().gold, gold + 7);
+    }
+
+    #[test]
+    fn reaching_the_goal_costs_a_life() {
+        let mut app = app();
+        let lives = app.world.resource::<PlayerStats>().lives;
+        let goal = app.world.resource::<Route>().goal;
+        // Flying over the goal counts too.
+        let arrived = app
+            .world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(
+                    goal + Vec3::new(0.1, 1.5, 0.0),
+                )),
+                TargetBundle::new(5.0, Vec3::Z, 0.2),
+            ))
+            .id();
+        let walking = spawn_target(&mut app, 0.0, 5.0);
+        app.update();
+
+        assert!(app.world.get_entity(arrived).is_none());
+        assert!(app.world.get_entity(walking).is_some());
+        assert_eq!(app.world.resource::<PlayerStats>().lives, lives - 1);
+    }
+
+    #[test]
+    fn ground_targets_pass_through_waypoints_around_obstacles() {
+        let mut app = app();
+        let waypoint = Vec3::new(0.0, 0.0, 2.0);
+        let route = Route {
+            spawn: Vec3::new(-2.0, 0.0, -2.0),
+            goal: Vec3::new(2.0, 0.0, -2.0),
+            ground_path: vec![waypoint],
+            ..default()
+        };
+        app.add_plugin(TransformPlugin)
+            .add_plugin(NavPlugin {})
+            .insert_resource(route.clone());
+        // In the way of the straight line from spawn to the waypoint.
+        app.world.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(-1.0, 0.0, 0.0)),
+            NavObstacle { radius: 0.4 },
+        ));
+        app.update();
+        let target = app
+            .world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(route.spawn)),
+                TargetBundle::new(5.0, Vec3::Z, 0.2),
+            ))
+            .id();
+
+        let mut closest = f32::MAX;
+        for _ in 0..200 {
+            app.update();
+            let Some(transform) = app.world.get::<Transform>(target) else {
+                break;
+            };
+            let position = transform.translation;
+            closest = closest.min((position - waypoint).xz().length());
+            assert!((position - Vec3::new(-1.0, 0.0, 0.0)).xz().length() > 0.3);
+        }
+        assert!(closest < 0.15, "missed the waypoint by {closest}");
+        assert!(
+            app.world.get_entity(target).is_none(),
+            "never reached the goal"
+        );
     }
 }
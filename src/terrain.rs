@@ -0,0 +1,273 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::{PrimitiveTopology, TextureFormat};
+use bevy::render::texture::{CompressedImageFormats, ImageType, TextureError};
+use bevy_rapier3d::prelude::Collider;
+
+// Heights from 0 to 1 sampled on a regular grid, with rows along z.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    samples: Vec<f32>,
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Decode(TextureError),
+    UnsupportedFormat(TextureFormat),
+    TooSmall,
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::Decode(err) => write!(f, "failed to decode heightmap: {err}"),
+            HeightmapError::UnsupportedFormat(format) => {
+                write!(f, "heightmap has unsupported format {format:?}")
+            }
+            HeightmapError::TooSmall => write!(f, "heightmap must be at least 2x2 pixels"),
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+impl From<TextureError> for HeightmapError {
+    fn from(err: TextureError) -> Self {
+        HeightmapError::Decode(err)
+    }
+}
+
+impl Heightmap {
+    pub fn new(width: usize, depth: usize, samples: Vec<f32>) -> Result<Self, HeightmapError> {
+        if width < 2 || depth < 2 {
+            return Err(HeightmapError::TooSmall);
+        }
+        assert_eq!(samples.len(), width * depth, "wrong number of samples");
+        Ok(Self {
+            width,
+            depth,
+            samples,
+        })
+    }
+
+    pub fn flat() -> Self {
+        Self {
+            width: 2,
+            depth: 2,
+            samples: vec![0.0; 4],
+        }
+    }
+
+    // Reads a grayscale image, where black is the lowest point and white the highest. Only the
+    // first channel of color images is used.
+    pub fn from_image(bytes: &[u8], extension: &str) -> Result<Self, HeightmapError> {
+        let image = Image::from_buffer(
+            bytes,
+            ImageType::Extension(extension),
+            CompressedImageFormats::NONE,
+            false,
+        )?;
+        let format = image.texture_descriptor.format;
+        let size = image.texture_descriptor.size;
+        let samples = match format {
+            TextureFormat::R8Unorm => image.data.iter().map(|&v| v as f32 / 255.0).collect(),
+            TextureFormat::Rg8Unorm => image.data.chunks(2).map(|v| v[0] as f32 / 255.0).collect(),
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {
+                image.data.chunks(4).map(|v| v[0] as f32 / 255.0).collect()
+            }
+            TextureFormat::R16Uint => image
+                .data
+                .chunks(2)
+                .map(|v| u16::from_le_bytes([v[0], v[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            _ => return Err(HeightmapError::UnsupportedFormat(format)),
+        };
+        Self::new(size.width as usize, size.height as usize, samples)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn sample(&self, x: usize, z: usize) -> f32 {
+        self.samples[z * self.width + x]
+    }
+}
+
+// The ground, a square centered on the origin with hills from its heightmap. Positions in level
+// files are heights above the terrain, and are snapped onto it when the level spawns.
+#[derive(Debug, Clone, Resource)]
+pub struct Terrain {
+    pub size: f32,
+    // World height of white in the heightmap.
+    pub height: f32,
+    pub heightmap: Heightmap,
+}
+
+impl Default for Terrain {
+    // Flat, and the size of the default nav grid.
+    fn default() -> Self {
+        Self {
+            size: 5.0,
+            height: 0.0,
+            heightmap: Heightmap::flat(),
+        }
+    }
+}
+
+impl Terrain {
+    fn spacing(&self) -> Vec2 {
+        let cells = Vec2::new(self.heightmap.width as f32, self.heightmap.depth as f32) - 1.0;
+        Vec2::splat(self.size) / cells
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> Vec3 {
+        let corner = Vec2::splat(-self.size / 2.0) + Vec2::new(x as f32, z as f32) * self.spacing();
+        Vec3::new(
+            corner.x,
+            self.heightmap.sample(x, z) * self.height,
+            corner.y,
+        )
+    }
+
+    // Height of the ground under `position`, following the same triangles as the mesh and
+    // collider. Off the edge, the height at the nearest edge.
+    pub fn height_at(&self, position: Vec3) -> f32 {
+        let cells = Vec2::new(self.heightmap.width as f32, self.heightmap.depth as f32) - 1.0;
+        let grid = ((Vec2::new(position.x, position.z) + self.size / 2.0) / self.spacing())
+            .clamp(Vec2::ZERO, cells);
+        let cell = grid.floor().min(cells - 1.0);
+        let (fx, fz) = (grid.x - cell.x, grid.y - cell.y);
+        let (x, z) = (cell.x as usize, cell.y as usize);
+        let h = |dx, dz| self.heightmap.sample(x + dx, z + dz);
+        // Cells are split along the diagonal from (1, 0) to (0, 1).
+        let sample = if fx + fz <= 1.0 {
+            h(0, 0) + fx * (h(1, 0) - h(0, 0)) + fz * (h(0, 1) - h(0, 0))
+        } else {
+            h(1, 1) + (1.0 - fx) * (h(0, 1) - h(1, 1)) + (1.0 - fz) * (h(1, 0) - h(1, 1))
+        };
+        sample * self.height
+    }
+
+    // Treats `position.y` as a height above the terrain, returning the point in the world.
+    pub fn snap(&self, position: Vec3) -> Vec3 {
+        position + Vec3::Y * self.height_at(position)
+    }
+
+    // How far `position` is above the terrain.
+    pub fn clearance(&self, position: Vec3) -> f32 {
+        position.y - self.height_at(position)
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        let (width, depth) = (self.heightmap.width, self.heightmap.depth);
+        let mut positions = Vec::with_capacity(width * depth);
+        let mut normals = Vec::with_capacity(width * depth);
+        let mut uvs = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                positions.push(self.vertex(x, z).to_array());
+                // Central differences, one-sided at the edges.
+                let dx =
+                    self.vertex((x + 1).min(width - 1), z) - self.vertex(x.saturating_sub(1), z);
+                let dz =
+                    self.vertex(x, (z + 1).min(depth - 1)) - self.vertex(x, z.saturating_sub(1));
+                normals.push(dz.cross(dx).normalize().to_array());
+                uvs.push([x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity((width - 1) * (depth - 1) * 6);
+        for z in 0..depth - 1 {
+            for x in 0..width - 1 {
+                let corner = (z * width + x) as u32;
+                let (right, below) = (corner + 1, corner + width as u32);
+                indices.extend([corner, below, right, right, below, below + 1]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+
+    pub fn collider(&self) -> Collider {
+        let (width, depth) = (self.heightmap.width, self.heightmap.depth);
+        // Rapier wants rows along z, in column-major order.
+        let heights = (0..width)
+            .flat_map(|x| (0..depth).map(move |z| (x, z)))
+            .map(|(x, z)| self.heightmap.sample(x, z))
+            .collect();
+        Collider::heightfield(
+            heights,
+            depth,
+            width,
+            Vec3::new(self.size, self.height, self.size),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rises along x and dips in the middle along z, so that mixing up the axes shows.
+    fn terrain() -> Terrain {
+        let samples = vec![
+            0.0, 0.5, 1.0, //
+            0.0, 0.0, 0.5, //
+            0.0, 0.5, 1.0, //
+        ];
+        Terrain {
+            size: 4.0,
+            height: 2.0,
+            heightmap: Heightmap::new(3, 3, samples).unwrap(),
+        }
+    }
+
+    #[test]
+    fn heights_interpolate_between_samples() {
+        let terrain = terrain();
+        assert_eq!(terrain.height_at(Vec3::new(-2.0, 0.0, -2.0)), 0.0);
+        assert_eq!(terrain.height_at(Vec3::new(2.0, 0.0, -2.0)), 2.0);
+        assert_eq!(terrain.height_at(Vec3::new(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(terrain.height_at(Vec3::new(1.0, 0.0, -2.0)), 1.5);
+        // Clamped to the edge.
+        assert_eq!(terrain.height_at(Vec3::new(10.0, 0.0, 10.0)), 2.0);
+        assert_eq!(terrain.snap(Vec3::new(2.0, 0.5, 0.0)).y, 1.5);
+    }
+
+    #[test]
+    fn collider_matches_heights() {
+        let terrain = terrain();
+        let collider = terrain.collider();
+        for x in [-1.9, -1.0, -0.3, 0.4, 1.2, 1.9] {
+            for z in [-1.9, -0.8, 0.1, 0.7, 1.9] {
+                let toi = collider
+                    .cast_local_ray(Vec3::new(x, 10.0, z), Vec3::NEG_Y, 20.0, true)
+                    .unwrap();
+                let expected = terrain.height_at(Vec3::new(x, 0.0, z));
+                assert!((10.0 - toi - expected).abs() < 1e-4, "at ({x}, {z})");
+            }
+        }
+    }
+
+    #[test]
+    fn default_heightmap_decodes() {
+        let bytes = std::fs::read("assets/levels/default.height.png").unwrap();
+        let heightmap = Heightmap::from_image(&bytes, "png").unwrap();
+        assert_eq!((heightmap.width(), heightmap.depth()), (33, 33));
+        assert!(heightmap.samples.iter().all(|h| (0.0..=1.0).contains(h)));
+    }
+}
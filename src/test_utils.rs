@@ -0,0 +1,87 @@
+// Shared fixtures for tests which run systems in an `App`.
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use crate::resources::*;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::Instant;
+use bevy_rapier3d::prelude::{NoUserData, RapierPhysicsPlugin};
+
+// An `App` whose clock only moves when it's updated, by `frame` each time.
+pub struct TestApp {
+    app: App,
+    now: Instant,
+    pub frame: Duration,
+}
+
+impl Default for TestApp {
+    fn default() -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        Self {
+            app,
+            now: Instant::now(),
+            frame: Duration::from_millis(50),
+        }
+    }
+}
+
+impl TestApp {
+    // Also sets up what Rapier needs, for tests with colliders and ray casts.
+    pub fn with_physics() -> Self {
+        let mut test_app = Self::default();
+        test_app
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_asset::<Mesh>()
+            .add_plugin(bevy::scene::ScenePlugin)
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+        test_app
+    }
+
+    pub fn update(&mut self) {
+        self.now += self.frame;
+        self.app
+            .insert_resource(TimeUpdateStrategy::ManualInstant(self.now));
+        self.app.update();
+    }
+
+    pub fn run(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.update();
+        }
+    }
+}
+
+impl Deref for TestApp {
+    type Target = App;
+
+    fn deref(&self) -> &App {
+        &self.app
+    }
+}
+
+impl DerefMut for TestApp {
+    fn deref_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+}
+
+// Empty handles, for systems which spawn scenes.
+pub fn game_assets() -> GameAssets {
+    GameAssets {
+        tower_base_scene: Handle::default(),
+        tower_scene: Handle::default(),
+        tomato_scene: Handle::default(),
+        font: Handle::default(),
+    }
+}
+
+// Every `E` sent since the app started, or since the events were last cleared.
+pub fn sent_events<E: Event + Clone>(app: &App) -> Vec<E> {
+    let events = app.world.resource::<Events<E>>();
+    events.get_reader().iter(events).cloned().collect()
+}
@@ -1,34 +1,288 @@
 use crate::bullet::*;
+use crate::collision::*;
 use crate::components::*;
+use crate::flow_field::*;
+use crate::health::*;
+use crate::health_bar::*;
+use crate::nav::*;
+use crate::picking::*;
 use crate::resources::*;
 use crate::target::*;
+use crate::terrain::*;
 
 use bevy::prelude::*;
 use bevy::utils::FloatOrd;
 use bevy_rapier3d::prelude::*;
 
+// Gold needed to build a tower.
+pub const TOWER_COST: u32 = 100;
+// Radius of the ground a tower blocks.
+pub const TOWER_RADIUS: f32 = 0.5;
+pub const TOWER_HEALTH: f32 = 50.0;
+
+// Request to build a tower at a point on the ground, which it is snapped onto. Rejected if the
+// player can't afford it, if it's outside the buildable areas, or if it would overlap another
+// tower or cut the route between spawn and goal, or any ground target's way to the goal.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildTower {
+    pub position: Vec3,
+    pub hits: TargetLayers,
+}
+
+// How a tower picks which target to shoot at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect, FromReflect)]
+pub enum TargetingMode {
+    #[default]
+    Closest,
+    // The target closest to the goal.
+    First,
+    Weakest,
+    Strongest,
+}
+
+impl TargetingMode {
+    pub fn next(self) -> Self {
+        match self {
+            TargetingMode::Closest => TargetingMode::First,
+            TargetingMode::First => TargetingMode::Weakest,
+            TargetingMode::Weakest => TargetingMode::Strongest,
+            TargetingMode::Strongest => TargetingMode::Closest,
+        }
+    }
+}
+
+// Which targets a tower is able to shoot at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect, FromReflect)]
+pub enum TargetLayers {
+    Ground,
+    Air,
+    #[default]
+    Both,
+}
+
+impl TargetLayers {
+    pub fn next(self) -> Self {
+        match self {
+            TargetLayers::Both => TargetLayers::Ground,
+            TargetLayers::Ground => TargetLayers::Air,
+            TargetLayers::Air => TargetLayers::Both,
+        }
+    }
+
+    pub fn can_hit(self, layer: PathLayer) -> bool {
+        matches!(
+            (self, layer),
+            (TargetLayers::Both, _)
+                | (TargetLayers::Ground, PathLayer::Ground)
+                | (TargetLayers::Air, PathLayer::Air)
+        )
+    }
+}
+
 #[derive(Debug, Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct Tower {
     pub shooting_timer: Timer,
     pub bullet_spawn_offset: Vec3,
+    pub damage: f32,
+    pub bullet_speed: f32,
+    pub level: u32,
+    // Gold spent on the tower, which determines the sell price.
+    pub value: u32,
+    pub targeting: TargetingMode,
+    pub hits: TargetLayers,
+    pub kills: u32,
+}
+
+impl Tower {
+    pub fn new(shooting_period: f32, bullet_spawn_offset: Vec3) -> Self {
+        Self {
+            shooting_timer: Timer::from_seconds(shooting_period, TimerMode::Repeating),
+            bullet_spawn_offset,
+            damage: 1.0,
+            bullet_speed: 5.0,
+            level: 1,
+            value: TOWER_COST,
+            targeting: TargetingMode::default(),
+            hits: TargetLayers::default(),
+            kills: 0,
+        }
+    }
+
+    pub fn upgrade_cost(&self) -> u32 {
+        50 * self.level
+    }
+
+    pub fn sell_price(&self) -> u32 {
+        self.value / 2
+    }
+
+    pub fn upgrade(&mut self) {
+        self.value += self.upgrade_cost();
+        self.level += 1;
+        self.damage += 1.0;
+        let period = self.shooting_timer.duration().mul_f32(0.9);
+        self.shooting_timer.set_duration(period);
+    }
 }
 
 pub struct TowerPlugin {}
 
 impl Plugin for TowerPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<Tower>().add_system(tower_shooting);
+        app.register_type::<Tower>()
+            .register_type::<TargetingMode>()
+            .register_type::<TargetLayers>()
+            .register_type::<BuildableAreas>()
+            .init_resource::<BuildableAreas>()
+            .add_event::<BuildTower>()
+            .add_system(build_towers)
+            .add_system(tower_shooting)
+            .add_system(count_kills);
+    }
+}
+
+pub fn spawn_tower(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    position: Vec3,
+    hits: TargetLayers,
+) -> Entity {
+    commands
+        .spawn(SceneBundle {
+            scene: assets.tower_scene.clone(),
+            transform: Transform::from_translation(position),
+            ..default()
+        })
+        .insert(Tower {
+            hits,
+            ..Tower::new(0.1, Vec3::new(0.0, 1.4, 0.0))
+        })
+        .insert(Selectable { priority: 0 })
+        .insert(Health::new(TOWER_HEALTH))
+        // Above the barrel.
+        .insert(ShowHealthBar { offset: 1.9 })
+        .insert(NavObstacle {
+            radius: TOWER_RADIUS,
+        })
+        .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
+        .with_children(|child_cmd| {
+            child_cmd
+                .spawn(Collider::cylinder(0.7, 0.6))
+                .insert(member_of(TOWERS | PICKABLE))
+                .insert(Transform::from_xyz(0.0, 0.7, 0.0))
+                .insert(Name::new("Hitbox"));
+        })
+        .insert(Name::new("Tower"))
+        .id()
+}
+
+fn build_towers(
+    mut commands: Commands,
+    mut events: EventReader<BuildTower>,
+    mut grid: ResMut<NavGrid>,
+    mut stats: ResMut<PlayerStats>,
+    route: Res<Route>,
+    buildable: Res<BuildableAreas>,
+    terrain: Res<Terrain>,
+    assets: Res<GameAssets>,
+    ground_targets: Query<
+        (&GlobalTransform, &PathFollower),
+        (With<Target>, Without<Flying>, Without<Dead>),
+    >,
+) {
+    for event in events.iter() {
+        if stats.gold < TOWER_COST {
+            info!("Not enough gold to build a tower");
+            continue;
+        }
+        let mut position = grid.snap_to_corner(event.position);
+        // Stand on the ground, whatever height the request was made at.
+        position.y = terrain.height_at(position);
+        if !buildable.allows(position) {
+            info!("Towers can't be built there");
+            continue;
+        }
+        let footprint = grid.cells_within(position, TOWER_RADIUS);
+        // The spawn, and every point ground targets must pass through.
+        let route_cells: Vec<IVec2> = std::iter::once(route.spawn)
+            .chain(route.ground_path.iter().copied())
+            .chain(std::iter::once(route.goal))
+            .map(|point| grid.nearest_cell(point))
+            .collect();
+        if footprint
+            .iter()
+            .any(|cell| grid.is_blocked(*cell) || route_cells.contains(cell))
+        {
+            info!("Can't build a tower there");
+            continue;
+        }
+        let blocked = grid.with_blocked(&footprint);
+        let fields = RouteFlowFields::new(&blocked, &route);
+        if !fields.reaches_goal(&blocked, route.spawn, 0) {
+            info!("A tower there would block the route");
+            continue;
+        }
+        // Targets on their way can be somewhere the route from spawn doesn't pass, like a pocket
+        // which the tower would close off.
+        if ground_targets.iter().any(|(transform, follower)| {
+            !fields.reaches_goal(&blocked, transform.translation(), follower.waypoint)
+        }) {
+            info!("A tower there would trap targets");
+            continue;
+        }
+
+        // Block the cells right away so that later requests this frame see the tower. Its
+        // obstacle blocks them again once it is spawned, and clears them when it is sold.
+        for cell in &footprint {
+            grid.set_blocked(*cell, true);
+        }
+        stats.gold -= TOWER_COST;
+        spawn_tower(&mut commands, &assets, position, event.hits);
     }
 }
 
+// Whether a tower can see from `from` to `to` without terrain, props or other towers in the way.
+// The tower's own hitbox, which it shoots from the top of, doesn't count.
+fn in_line_of_sight(
+    rapier_context: &RapierContext,
+    tower: Entity,
+    parent_query: &Query<&Parent>,
+    from: Vec3,
+    to: Vec3,
+) -> bool {
+    let Some(direction) = (to - from).try_normalize() else {
+        return true;
+    };
+    let not_own_hitbox = |collider| {
+        parent_query
+            .get(collider)
+            .map_or(true, |parent| parent.get() != tower)
+    };
+    let filter = filter_for(BLOCKS_SIGHT).predicate(&not_own_hitbox);
+    rapier_context
+        .cast_ray(from, direction, from.distance(to), true, filter)
+        .is_none()
+}
+
+// Towers shoot at the best target by their targeting mode which they can see. Without physics,
+// every target counts as visible.
 fn tower_shooting(
     mut commands: Commands,
     mut towers: Query<(Entity, &mut Tower, &GlobalTransform)>,
-    targets: Query<&GlobalTransform, With<Target>>,
+    targets: Query<
+        (&GlobalTransform, &Health, &PathFollower, Option<&Flying>),
+        (With<Target>, Without<Dead>),
+    >,
+    route: Res<Route>,
+    flow_fields: Option<Res<RouteFlowFields>>,
+    grid: Option<Res<NavGrid>>,
+    rapier_context: Option<Res<RapierContext>>,
+    parent_query: Query<&Parent>,
     bullet_assets: Res<GameAssets>,
     time: Res<Time>,
 ) {
+    let flow = flow_fields.as_deref().zip(grid.as_deref());
     for (entity, mut tower, transform) in &mut towers {
         tower.shooting_timer.tick(time.delta());
         if !tower.shooting_timer.just_finished() {
@@ -36,16 +290,42 @@ fn tower_shooting(
         }
 
         let bullet_spawn_loc = transform.translation() + tower.bullet_spawn_offset;
-        let towards_enemy = targets
+        let mut candidates: Vec<_> = targets
             .iter()
-            .min_by_key(|target| FloatOrd(bullet_spawn_loc.distance(target.translation())))
-            .map(|closest_target| closest_target.translation() - bullet_spawn_loc);
+            .filter(|(_, _, _, flying)| tower.hits.can_hit(Flying::layer(*flying)))
+            .collect();
+        // Ray cast to the best candidates first, so that usually only one is needed.
+        candidates.sort_by_key(|(target, health, follower, flying)| match tower.targeting {
+            TargetingMode::Closest => FloatOrd(bullet_spawn_loc.distance(target.translation())),
+            TargetingMode::First => FloatOrd(follower.remaining_distance(
+                &route,
+                Flying::layer(*flying),
+                target.translation(),
+                flow,
+            )),
+            TargetingMode::Weakest => FloatOrd(health.val),
+            TargetingMode::Strongest => FloatOrd(-health.val),
+        });
+        let towards_enemy = candidates
+            .into_iter()
+            .map(|(target, ..)| target.translation())
+            .find(|target| {
+                rapier_context.as_deref().is_none_or(|rapier_context| {
+                    in_line_of_sight(
+                        rapier_context,
+                        entity,
+                        &parent_query,
+                        bullet_spawn_loc,
+                        *target,
+                    )
+                })
+            })
+            .map(|target| target - bullet_spawn_loc);
         let direction = match towards_enemy {
             Some(enemy) => enemy.normalize(),
             None => continue,
         };
-        let speed = 5.0;
-        let damage = 1.0;
+        let (speed, damage) = (tower.bullet_speed, tower.damage);
         commands.entity(entity).with_children(|child_builder| {
             child_builder
                 .spawn(SceneBundle {
@@ -55,17 +335,187 @@ fn tower_shooting(
                     transform: Transform::from_translation(tower.bullet_spawn_offset),
                     ..default()
                 })
-                .insert(BulletBundle::new(direction * speed, damage))
+                .insert(BulletBundle::new(direction * speed, damage, entity))
                 .insert(Lifetime {
                     timer: Timer::from_seconds(10.0, TimerMode::Once),
                 })
                 .insert(RigidBody::Fixed) // Seems needed for the Collider transform.
                 .with_children(|child_cmd| {
                     child_cmd
-                        .spawn(Collider::ball(0.07))
+                        .spawn(Collider::ball(BULLET_RADIUS))
+                        .insert(member_of(PROJECTILES))
                         .insert(Name::new("Hitbox"));
                 })
                 .insert(Name::new("Bullet"));
         });
     }
 }
+
+fn count_kills(mut died: EventReader<Died>, mut towers: Query<&mut Tower>) {
+    for event in died.iter() {
+        if let Some(mut tower) = event.killer.and_then(|killer| towers.get_mut(killer).ok()) {
+            tower.kills += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn app() -> TestApp {
+        let mut app = TestApp::with_physics();
+        app.add_plugin(HealthPlugin {})
+            .add_plugin(NavPlugin {})
+            .add_plugin(TowerPlugin {})
+            .insert_resource(Route::default())
+            .insert_resource(PlayerStats::default())
+            .insert_resource(game_assets());
+        app
+    }
+
+    fn spawn_target(app: &mut App, position: Vec3) -> Entity {
+        app.world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(position)),
+                TargetBundle::new(10.0, Vec3::ZERO, 0.2),
+                RigidBody::Fixed,
+            ))
+            .with_children(|children| {
+                children.spawn((
+                    TransformBundle::default(),
+                    Collider::ball(0.2),
+                    member_of(TARGETS),
+                ));
+            })
+            .id()
+    }
+
+    #[test]
+    fn towers_skip_targets_behind_walls() {
+        let mut app = app();
+        let tower = app
+            .world
+            .spawn((TransformBundle::default(), Tower::new(0.1, Vec3::Y)))
+            .id();
+        // A wall between the tower and the closest target, which another target hides behind too.
+        app.world.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(1.0, 1.0, 0.0)),
+            Collider::cuboid(0.1, 2.0, 2.0),
+            member_of(PROPS),
+        ));
+        spawn_target(&mut app, Vec3::new(2.0, 1.0, 0.0));
+        spawn_target(&mut app, Vec3::new(1.5, 1.0, 0.2));
+        spawn_target(&mut app, Vec3::new(-3.0, 1.0, 0.0));
+
+        app.run(5);
+
+        let mut bullets = app.world.query::<(&Bullet, &crate::components::Velocity)>();
+        let directions: Vec<Vec3> = bullets
+            .iter(&app.world)
+            .map(|(bullet, velocity)| {
+                assert_eq!(bullet.source, Some(tower));
+                velocity.val.normalize()
+            })
+            .collect();
+        assert!(!directions.is_empty());
+        // Only ever at the visible target, despite it being furthest away.
+        assert!(directions.iter().all(|direction| direction.x < -0.99));
+    }
+
+    #[test]
+    fn building_spends_gold_until_it_runs_out() {
+        let mut app = app();
+        app.insert_resource(PlayerStats {
+            gold: TOWER_COST * 2 + 10,
+            lives: 20,
+        });
+        app.update();
+        for z in [-1.5, 0.0, 1.0] {
+            app.world.send_event(BuildTower {
+                position: Vec3::new(0.0, 0.0, z),
+                hits: TargetLayers::Both,
+            });
+            app.update();
+        }
+        let towers = app.world.query::<&Tower>().iter(&app.world).count();
+        assert_eq!(towers, 2);
+        assert_eq!(app.world.resource::<PlayerStats>().gold, 10);
+    }
+
+    #[test]
+    fn selling_refunds_half_of_everything_spent() {
+        let mut tower = Tower::new(1.0, Vec3::Y);
+        assert_eq!(tower.sell_price(), TOWER_COST / 2);
+        let cost = tower.upgrade_cost();
+        tower.upgrade();
+        assert_eq!(tower.level, 2);
+        assert_eq!(tower.sell_price(), (TOWER_COST + cost) / 2);
+        assert!(tower.upgrade_cost() > cost);
+    }
+
+    fn bullets_fired(app: &mut App) -> usize {
+        app.world.query::<&Bullet>().iter(&app.world).count()
+    }
+
+    #[test]
+    fn ground_only_towers_ignore_flyers() {
+        for (hits, fires) in [(TargetLayers::Ground, false), (TargetLayers::Both, true)] {
+            let mut app = app();
+            app.world.spawn((
+                TransformBundle::default(),
+                Tower {
+                    hits,
+                    ..Tower::new(0.1, Vec3::Y)
+                },
+            ));
+            let flyer = spawn_target(&mut app, Vec3::new(2.0, 1.0, 0.0));
+            app.world.entity_mut(flyer).insert(Flying { altitude: 1.0 });
+            app.run(5);
+            assert_eq!(bullets_fired(&mut app) > 0, fires, "{hits:?}");
+        }
+    }
+
+    #[test]
+    fn towers_cant_trap_ground_targets() {
+        for (flying, built) in [(false, 0), (true, 1)] {
+            let mut app = app();
+            // Straight from spawn to goal, so that only the targets need the gap.
+            app.insert_resource(Route {
+                ground_path: Vec::new(),
+                ..default()
+            });
+            app.update();
+            // A wall across the map, with a gap which a tower at `gap` exactly fills. Targets
+            // below it would be cut off from the goal, while the route above is left alone.
+            let gap = Vec3::new(0.0, 0.0, -1.0);
+            {
+                let mut grid = app.world.resource_mut::<NavGrid>();
+                let footprint = grid.cells_within(gap, TOWER_RADIUS);
+                let row = grid.nearest_cell(gap).y;
+                for x in 0..grid.size().x {
+                    let cell = IVec2::new(x, row);
+                    if !footprint.contains(&cell) {
+                        grid.set_blocked(cell, true);
+                    }
+                }
+            }
+            let target = spawn_target(&mut app, Vec3::new(0.0, 0.45, -2.0));
+            if flying {
+                app.world
+                    .entity_mut(target)
+                    .insert(Flying { altitude: 1.0 });
+            }
+            app.update();
+
+            app.world.send_event(BuildTower {
+                position: gap,
+                hits: TargetLayers::Both,
+            });
+            app.update();
+            let towers = app.world.query::<&Tower>().iter(&app.world).count();
+            assert_eq!(towers, built, "flying: {flying}");
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use crate::enemies::*;
+use crate::resources::*;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct Wave {
+    pub kind: EnemyKind,
+    pub count: u32,
+    // Seconds between each target in the wave.
+    pub interval: f32,
+    // Scales the health from the enemy's definition, so later waves are tougher.
+    pub health_multiplier: f32,
+    // Spawned after the rest of the wave.
+    #[serde(default)]
+    pub boss: Option<EnemyKind>,
+}
+
+impl Wave {
+    pub fn spawn_count(&self) -> u32 {
+        self.count + self.boss.is_some() as u32
+    }
+
+    // Kind of the `index`th target spawned in the wave.
+    pub fn kind_at(&self, index: u32) -> EnemyKind {
+        match self.boss {
+            Some(boss) if index >= self.count => boss,
+            _ => self.kind,
+        }
+    }
+}
+
+#[derive(Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Waves {
+    pub waves: Vec<Wave>,
+    // Number of waves which have started.
+    pub started: usize,
+    // Targets spawned so far in the latest wave.
+    pub spawned: u32,
+    pub spawn_timer: Timer,
+    // Time until the next wave starts. Only ticks once the latest wave is fully spawned.
+    pub countdown: Timer,
+}
+
+impl Waves {
+    pub fn new(waves: Vec<Wave>, first_wave_delay: f32, wave_gap: f32) -> Self {
+        let mut countdown = Timer::from_seconds(wave_gap, TimerMode::Once);
+        countdown.set_elapsed(
+            countdown
+                .duration()
+                .saturating_sub(Duration::from_secs_f32(first_wave_delay)),
+        );
+        Self {
+            waves,
+            started: 0,
+            spawned: 0,
+            spawn_timer: Timer::default(),
+            countdown,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.waves.len()
+    }
+
+    pub fn current(&self) -> Option<&Wave> {
+        self.started.checked_sub(1).and_then(|i| self.waves.get(i))
+    }
+
+    fn spawning(&self) -> bool {
+        matches!(self.current(), Some(wave) if self.spawned < wave.spawn_count())
+    }
+
+    // Seconds until the next wave, if there is one and it is counting down.
+    pub fn time_to_next_wave(&self) -> Option<f32> {
+        if self.started >= self.total() || self.spawning() {
+            return None;
+        }
+        Some(self.countdown.remaining_secs())
+    }
+}
+
+impl Default for Waves {
+    fn default() -> Self {
+        let kinds = [
+            EnemyKind::Runner,
+            EnemyKind::Tank,
+            EnemyKind::Splitter,
+            EnemyKind::Flyer,
+            EnemyKind::Healer,
+            EnemyKind::Runner,
+            EnemyKind::Tank,
+        ];
+        let mut waves: Vec<Wave> = kinds
+            .into_iter()
+            .enumerate()
+            .map(|(i, kind)| Wave {
+                kind,
+                count: 5 + i as u32,
+                interval: 1.0,
+                health_multiplier: 1.0 + 0.25 * i as f32,
+                boss: None,
+            })
+            .collect();
+        if let Some(last) = waves.last_mut() {
+            last.boss = Some(EnemyKind::Boss);
+        }
+        Waves::new(waves, 3.0, 15.0)
+    }
+}
+
+pub struct WavesPlugin {}
+
+impl Plugin for WavesPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Waves>()
+            .insert_resource(Waves::default())
+            .add_system(spawn_waves);
+    }
+}
+
+fn spawn_waves(
+    mut commands: Commands,
+    mut waves: ResMut<Waves>,
+    route: Res<Route>,
+    catalog: Res<EnemyCatalog>,
+    time: Res<Time>,
+) {
+    if waves.spawning() {
+        waves.spawn_timer.tick(time.delta());
+        if !waves.spawn_timer.just_finished() {
+            return;
+        }
+    } else {
+        if waves.started >= waves.total() {
+            return;
+        }
+        waves.countdown.tick(time.delta());
+        if !waves.countdown.just_finished() {
+            return;
+        }
+        // Start the next wave, spawning its first target right away.
+        waves.countdown.reset();
+        waves.started += 1;
+        waves.spawned = 0;
+        let interval = waves.waves[waves.started - 1].interval;
+        waves.spawn_timer = Timer::from_seconds(interval, TimerMode::Repeating);
+    }
+
+    let wave = waves.waves[waves.started - 1].clone();
+    // Targets steer along their path once spawned, so the initial direction only matters for
+    // the first frame.
+    spawn_enemy(
+        &mut commands,
+        &catalog,
+        wave.kind_at(waves.spawned),
+        route.spawn,
+        route.goal - route.spawn,
+        wave.health_multiplier,
+    );
+    waves.spawned += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn wave(count: u32) -> Wave {
+        Wave {
+            kind: EnemyKind::Runner,
+            count,
+            interval: 1.0,
+            health_multiplier: 1.0,
+            boss: None,
+        }
+    }
+
+    fn enemies(app: &mut App) -> usize {
+        app.world.query::<&Enemy>().iter(&app.world).count()
+    }
+
+    #[test]
+    fn waves_start_after_their_delay_and_spawn_at_intervals() {
+        let mut app = TestApp::default();
+        app.frame = Duration::from_millis(500);
+        app.add_plugin(WavesPlugin {})
+            .insert_resource(Route::default())
+            .insert_resource(EnemyCatalog::default())
+            .insert_resource(Waves::new(vec![wave(3), wave(1)], 2.0, 5.0));
+        // The first update only initializes time.
+        app.update();
+
+        let mut counts = Vec::new();
+        for _ in 0..20 {
+            app.update();
+            counts.push(enemies(&mut app));
+        }
+        // The first wave starts 2s in, with one enemy a second. The next starts 5s after the
+        // first is fully spawned.
+        assert_eq!(
+            counts,
+            [0, 0, 0, 1, 1, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4]
+        );
+        let waves = app.world.resource::<Waves>();
+        assert_eq!(waves.started, 2);
+        assert_eq!(waves.time_to_next_wave(), None);
+    }
+
+    #[test]
+    fn countdown_is_hidden_while_a_wave_spawns() {
+        let mut waves = Waves::new(vec![wave(2)], 3.0, 10.0);
+        assert_eq!(waves.time_to_next_wave(), Some(3.0));
+        waves.started = 1;
+        assert_eq!(waves.time_to_next_wave(), None);
+        waves.spawned = 2;
+        // That was the last wave.
+        assert_eq!(waves.time_to_next_wave(), None);
+    }
+}